@@ -1,5 +1,6 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
+//! This build script copies the `memory.x` file matching the enabled features
+//! from the `memory/` directory into a directory where the linker can always
+//! find it at build time.
 //! For many projects this is optional, as the linker always searches the
 //! project root directory -- wherever `Cargo.toml` is. However, if you
 //! are using a workspace or have a more complicated build setup, this
@@ -10,6 +11,7 @@
 
 use std::env;
 use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 fn copy_config(out: &PathBuf, file: &str) {
@@ -29,6 +31,19 @@ fn copy_config(out: &PathBuf, file: &str) {
     }
 }
 
+fn copy_optional_config(out: &PathBuf, file: &str) {
+    if Path::new(file).exists() {
+        fs::copy(file, out.join(file)).expect("error copying file");
+        println!("cargo:rerun-if-changed={}", file);
+    } else {
+        fs::write(out.join(file), b"").expect("error creating empty config file");
+        // A missing file is always reported as changed, so watch for it being added.
+        if Path::new("config").exists() {
+            println!("cargo:rerun-if-changed=config");
+        }
+    }
+}
+
 fn main() {
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
@@ -36,12 +51,42 @@ fn main() {
     fs::create_dir_all(out.join("config")).expect("error creating output directory for config");
     copy_config(&out, "config/wifi.ssid.txt");
     copy_config(&out, "config/wifi.password.txt");
-    copy_config(&out, "config/username.txt");
-    copy_config(&out, "config/password.txt");
+    // The server must be verified unless explicitly built without
+    if env::var_os("CARGO_FEATURE_INSECURE").is_some() {
+        copy_optional_config(&out, "config/server-key.sha256");
+    } else {
+        copy_config(&out, "config/server-key.sha256");
+    }
+
+    // Devices authenticate either with a client certificate or with a username and password
+    if Path::new("config/client-cert.der").exists() {
+        copy_config(&out, "config/client-key.der");
+        copy_optional_config(&out, "config/client-cert.der");
+        copy_optional_config(&out, "config/username.txt");
+        copy_optional_config(&out, "config/password.txt");
+    } else {
+        copy_optional_config(&out, "config/client-cert.der");
+        copy_optional_config(&out, "config/client-key.der");
+        copy_config(&out, "config/username.txt");
+        copy_config(&out, "config/password.txt");
+    }
+
+    // The gateway runs alongside the SoftDevice, which claims the start of flash and RAM.
+    let memory = if env::var_os("CARGO_FEATURE_GATEWAY").is_some() {
+        "memory/nrf52833-s140.x"
+    } else {
+        "memory/nrf52833.x"
+    };
+    fs::File::create(out.join("memory.x"))
+        .expect("error creating memory.x")
+        .write_all(&fs::read(memory).expect("error reading memory layout"))
+        .expect("error writing memory.x");
+    println!("cargo:rustc-link-search={}", out.display());
 
     // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
-    // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    // any file in the project changes. By specifying the memory
+    // layouts here, we ensure the build script is only re-run when
+    // one of them is changed.
+    println!("cargo:rerun-if-changed=memory/nrf52833.x");
+    println!("cargo:rerun-if-changed=memory/nrf52833-s140.x");
 }
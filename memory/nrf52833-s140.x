@@ -0,0 +1,9 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* Layout for running alongside the S140 7.x SoftDevice (gateway feature). */
+  /* The SoftDevice occupies the start of flash and RAM; adjust RAM ORIGIN if
+     the SoftDevice reports that it needs more RAM for the enabled roles. */
+  FLASH : ORIGIN = 0x00027000, LENGTH = 512K - 156K
+  RAM : ORIGIN = 0x20003400, LENGTH = 128K - 13K
+}
@@ -0,0 +1,247 @@
+use super::climate::{ClimateError, Reading};
+use embassy::time::{Duration, Timer};
+use embassy::traits::i2c::I2c;
+
+const CHIP_ID: u8 = 0x60;
+
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_CALIB_00: u8 = 0x88;
+const REG_CALIB_26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_PRESS_MSB: u8 = 0xF7;
+
+/// Trimming parameters stored in each sensor, used to compensate its readings.
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub t1: u16,
+    pub t2: i16,
+    pub t3: i16,
+    pub p1: u16,
+    pub p2: i16,
+    pub p3: i16,
+    pub p4: i16,
+    pub p5: i16,
+    pub p6: i16,
+    pub p7: i16,
+    pub p8: i16,
+    pub p9: i16,
+    pub h1: u8,
+    pub h2: i16,
+    pub h3: u8,
+    pub h4: i16,
+    pub h5: i16,
+    pub h6: i8,
+}
+
+impl Calibration {
+    /// Parse the parameters read from 0x88-0xA1 and 0xE1-0xE7.
+    pub fn parse(a: &[u8; 26], b: &[u8; 7]) -> Self {
+        let u = |i: usize| u16::from_le_bytes([a[i], a[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([a[i], a[i + 1]]);
+        Self {
+            t1: u(0),
+            t2: s(2),
+            t3: s(4),
+            p1: u(6),
+            p2: s(8),
+            p3: s(10),
+            p4: s(12),
+            p5: s(14),
+            p6: s(16),
+            p7: s(18),
+            p8: s(20),
+            p9: s(22),
+            h1: a[25],
+            h2: i16::from_le_bytes([b[0], b[1]]),
+            h3: b[2],
+            h4: ((b[3] as i8 as i16) << 4) | (b[4] & 0x0F) as i16,
+            h5: ((b[5] as i8 as i16) << 4) | (b[4] >> 4) as i16,
+            h6: b[6] as i8,
+        }
+    }
+
+    /// Compensate the raw readings with the integer formulas of the datasheet, returning
+    /// the temperature in °C, the pressure in Pa and the relative humidity in percent.
+    pub fn compensate(&self, adc_t: i32, adc_p: i32, adc_h: i32) -> (f32, f32, f32) {
+        let var1 = (((adc_t >> 3) - ((self.t1 as i32) << 1)) * self.t2 as i32) >> 11;
+        let var2 = (((((adc_t >> 4) - self.t1 as i32) * ((adc_t >> 4) - self.t1 as i32)) >> 12)
+            * self.t3 as i32)
+            >> 14;
+        let t_fine = var1 + var2;
+        let temperature = ((t_fine * 5 + 128) >> 8) as f32 / 100.0;
+
+        (
+            temperature,
+            self.pressure(t_fine, adc_p) as f32 / 256.0,
+            self.humidity(t_fine, adc_h) as f32 / 1024.0,
+        )
+    }
+
+    /// Pressure in Pa as a Q24.8 fixed point number.
+    fn pressure(&self, t_fine: i32, adc_p: i32) -> u32 {
+        let mut var1 = t_fine as i64 - 128000;
+        let mut var2 = var1 * var1 * self.p6 as i64;
+        var2 += (var1 * self.p5 as i64) << 17;
+        var2 += (self.p4 as i64) << 35;
+        var1 = ((var1 * var1 * self.p3 as i64) >> 8) + ((var1 * self.p2 as i64) << 12);
+        var1 = (((1i64 << 47) + var1) * self.p1 as i64) >> 33;
+        if var1 == 0 {
+            return 0;
+        }
+        let mut p = 1048576 - adc_p as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (self.p9 as i64 * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (self.p8 as i64 * p) >> 19;
+        (((p + var1 + var2) >> 8) + ((self.p7 as i64) << 4)) as u32
+    }
+
+    /// Relative humidity in percent as a Q22.10 fixed point number.
+    fn humidity(&self, t_fine: i32, adc_h: i32) -> u32 {
+        let mut v = t_fine - 76800;
+        v = (((adc_h << 14) - ((self.h4 as i32) << 20) - (self.h5 as i32 * v) + 16384) >> 15)
+            * (((((((v * self.h6 as i32) >> 10) * (((v * self.h3 as i32) >> 11) + 32768)) >> 10)
+                + 2097152)
+                * self.h2 as i32
+                + 8192)
+                >> 14);
+        v -= ((((v >> 15) * (v >> 15)) >> 7) * self.h1 as i32) >> 4;
+        let v = if v < 0 {
+            0
+        } else if v > 419430400 {
+            419430400
+        } else {
+            v
+        };
+        (v >> 12) as u32
+    }
+}
+
+/// BME280 temperature, humidity and pressure sensor, taking measurements in forced mode
+/// with 1x oversampling.
+pub struct Bme280 {
+    address: u8,
+    calibration: Option<Calibration>,
+}
+
+impl Bme280 {
+    pub const fn new(address: u8) -> Self {
+        Self {
+            address,
+            calibration: None,
+        }
+    }
+
+    async fn calibrate<I: I2c>(
+        &mut self,
+        i2c: &mut I,
+    ) -> Result<Calibration, ClimateError<I::Error>> {
+        let mut id = [0; 1];
+        i2c.write_read(self.address, &[REG_CHIP_ID], &mut id)
+            .await
+            .map_err(ClimateError::I2c)?;
+        if id[0] != CHIP_ID {
+            return Err(ClimateError::UnknownDevice);
+        }
+
+        let mut a = [0; 26];
+        let mut b = [0; 7];
+        i2c.write_read(self.address, &[REG_CALIB_00], &mut a)
+            .await
+            .map_err(ClimateError::I2c)?;
+        i2c.write_read(self.address, &[REG_CALIB_26], &mut b)
+            .await
+            .map_err(ClimateError::I2c)?;
+        let calibration = Calibration::parse(&a, &b);
+        self.calibration.replace(calibration);
+        Ok(calibration)
+    }
+
+    pub async fn read<I: I2c>(&mut self, i2c: &mut I) -> Result<Reading, ClimateError<I::Error>> {
+        let calibration = match self.calibration {
+            Some(calibration) => calibration,
+            None => self.calibrate(i2c).await?,
+        };
+
+        // Humidity oversampling only takes effect once ctrl_meas is written.
+        i2c.write(self.address, &[REG_CTRL_HUM, 0b001])
+            .await
+            .map_err(ClimateError::I2c)?;
+        // Temperature and pressure oversampling 1x, forced mode.
+        i2c.write(self.address, &[REG_CTRL_MEAS, 0b0010_0101])
+            .await
+            .map_err(ClimateError::I2c)?;
+        Timer::after(Duration::from_millis(10)).await;
+
+        let mut data = [0; 8];
+        i2c.write_read(self.address, &[REG_PRESS_MSB], &mut data)
+            .await
+            .map_err(ClimateError::I2c)?;
+        let adc_p = (data[0] as i32) << 12 | (data[1] as i32) << 4 | (data[2] as i32) >> 4;
+        let adc_t = (data[3] as i32) << 12 | (data[4] as i32) << 4 | (data[5] as i32) >> 4;
+        let adc_h = (data[6] as i32) << 8 | data[7] as i32;
+
+        let (temperature, pressure, humidity) = calibration.compensate(adc_t, adc_p, adc_h);
+        Ok(Reading {
+            temperature,
+            humidity,
+            pressure: Some(pressure / 100.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_near;
+
+    /// Trimming parameters of the compensation example in the Bosch datasheet, with
+    /// humidity parameters of a typical sensor.
+    fn calibration() -> Calibration {
+        let mut a = [0; 26];
+        let words: [i32; 12] = [
+            27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            a[i * 2..i * 2 + 2].copy_from_slice(&(*word as u16).to_le_bytes());
+        }
+        a[25] = 75;
+        // H2 = 362, H3 = 0, H4 = 324 and H5 = 0 packed into 12 bits each, H6 = 30.
+        let b = [0x6A, 0x01, 0x00, 0x14, 0x04, 0x00, 0x1E];
+        Calibration::parse(&a, &b)
+    }
+
+    #[test]
+    fn parse() {
+        let c = calibration();
+        assert_eq!((c.t1, c.t2, c.t3), (27504, 26435, -1000));
+        assert_eq!((c.p1, c.p2, c.p9), (36477, -10685, 6000));
+        assert_eq!((c.h1, c.h2, c.h3), (75, 362, 0));
+        assert_eq!((c.h4, c.h5, c.h6), (324, 0, 30));
+    }
+
+    #[test]
+    fn parse_negative_humidity_parameters() {
+        let c = Calibration::parse(&[0; 26], &[0, 0, 0, 0xFE, 0x3F, 0xFF, 0xF6]);
+        assert_eq!((c.h4, c.h5, c.h6), (-32 + 0xF, -16 + 0x3, -10));
+    }
+
+    #[test]
+    fn compensate_datasheet_example() {
+        let (temperature, pressure, _) = calibration().compensate(519888, 415148, 0);
+        // 25.08 °C and 100653.27 Pa as given in the datasheet, the integer formulas land
+        // within a fraction of a pascal of the floating point ones.
+        assert_near(temperature, 25.08, 0.001);
+        assert_near(pressure, 100653.27, 0.05);
+    }
+
+    #[test]
+    fn compensate_humidity() {
+        // Expected values from the floating point formula of the datasheet.
+        let c = calibration();
+        assert_near(c.compensate(519888, 415148, 27000).2, 35.22, 0.1);
+        assert_near(c.compensate(519888, 415148, 30000).2, 51.96, 0.1);
+        assert_eq!(c.compensate(519888, 415148, 0).2, 0.0);
+        assert_eq!(c.compensate(519888, 415148, 65535).2, 100.0);
+    }
+}
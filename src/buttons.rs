@@ -0,0 +1,130 @@
+use super::display::{DisplayActor, DisplayCommand};
+use super::gesture::{Gesture, GestureDetector};
+use super::plant_monitor::Command;
+use core::future::Future;
+use core::pin::Pin;
+use drogue_device::*;
+use embassy::time::{Duration, Instant, Timer};
+use embassy::traits::gpio::WaitForAnyEdge;
+use embassy_nrf::{
+    gpiote::PortInput,
+    peripherals::{P0_14, P0_23},
+};
+use embedded_hal::digital::v2::InputPin;
+use futures::{future::select, pin_mut};
+
+/// Time for the button contacts to settle after an edge.
+const DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// Turns presses of buttons A and B into actions:
+///
+/// * A: take a measurement now
+/// * B: show the next display mode
+/// * A+B: show the statistics of the last 24 hours
+/// * Hold A: calibrate the soil probe, first in dry and then in wet soil
+/// * Hold B: snooze the dry soil alarm
+/// * Hold A+B: factory reset
+#[rustfmt::skip]
+pub struct Buttons<M>
+where
+    M: Actor<Message<'static> = Command> + 'static,
+{
+    a: PortInput<'static, P0_14>,
+    b: PortInput<'static, P0_23>,
+    gestures: GestureDetector,
+    monitor: Option<Address<'static, M>>,
+    display: Option<Address<'static, DisplayActor>>,
+}
+
+#[rustfmt::skip]
+impl<M> Buttons<M>
+where
+    M: Actor<Message<'static> = Command> + 'static,
+{
+    /// Create buttons recognizing presses held longer than `long_press` as long presses.
+    pub fn new(
+        a: PortInput<'static, P0_14>,
+        b: PortInput<'static, P0_23>,
+        long_press: Duration,
+    ) -> Self {
+        Self {
+            a,
+            b,
+            gestures: GestureDetector::new(long_press.as_millis(), DEBOUNCE.as_millis()),
+            monitor: None,
+            display: None,
+        }
+    }
+
+    fn perform(&self, gesture: Gesture) {
+        log::debug!("Gesture {:?}", gesture);
+        match gesture {
+            Gesture::ShortA => {
+                self.monitor.unwrap().notify(Command::TakeMeasurement).ok();
+            }
+            Gesture::ShortB => {
+                self.display.unwrap().notify(DisplayCommand::NextMode).ok();
+            }
+            Gesture::LongA => {
+                self.monitor.unwrap().notify(Command::Calibrate).ok();
+            }
+            Gesture::LongB => {
+                self.monitor.unwrap().notify(Command::Snooze).ok();
+            }
+            Gesture::ShortAB => {
+                self.monitor.unwrap().notify(Command::ShowStatistics).ok();
+            }
+            Gesture::LongAB => {
+                // Settings only live in RAM, so restarting restores the configured defaults.
+                log::info!("Factory reset");
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl<M> Actor for Buttons<M>
+where
+    M: Actor<Message<'static> = Command> + 'static,
+{
+    type Configuration = (Address<'static, M>, Address<'static, DisplayActor>);
+
+    type Message<'m> = ();
+    type OnStartFuture<'m> = impl Future<Output = ()> + 'm;
+    type OnMessageFuture<'m> = impl Future<Output = ()> + 'm;
+
+    fn on_mount(&mut self, _: Address<'static, Self>, config: Self::Configuration) {
+        self.monitor.replace(config.0);
+        self.display.replace(config.1);
+    }
+
+    fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
+        async move {
+            let this = unsafe { self.get_unchecked_mut() };
+            loop {
+                {
+                    let a = this.a.wait_for_any_edge();
+                    let b = this.b.wait_for_any_edge();
+                    pin_mut!(a, b);
+                    select(a, b).await;
+                }
+                Timer::after(DEBOUNCE).await;
+
+                // The buttons pull the pins low when pressed.
+                let a = this.a.is_low().unwrap_or(false);
+                let b = this.b.is_low().unwrap_or(false);
+                if let Some(gesture) = this.gestures.update(a, b, Instant::now().as_millis()) {
+                    this.perform(gesture);
+                }
+            }
+        }
+    }
+
+    fn on_message<'m>(
+        self: Pin<&'m mut Self>,
+        _: Self::Message<'m>,
+    ) -> Self::OnMessageFuture<'m> {
+        async move {}
+    }
+}
@@ -0,0 +1,65 @@
+use super::bme280::Bme280;
+use super::dht11::{self, Delay, DhtError};
+use super::sht;
+use embassy::traits::i2c::I2c;
+use embassy_nrf::gpio::{FlexPin, Pin};
+
+#[derive(Clone, Copy)]
+pub struct Reading {
+    /// Temperature in °C.
+    pub temperature: f32,
+    /// Relative humidity in percent.
+    pub humidity: f32,
+    /// Air pressure in hPa, for sensors measuring it.
+    pub pressure: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum ClimateError<E> {
+    Dht11(DhtError),
+    I2c(E),
+    /// The data read did not match its checksum.
+    Crc,
+    /// The device does not identify as the expected sensor.
+    UnknownDevice,
+}
+
+/// Sensors measuring the temperature and humidity of the air.
+pub enum ClimateSensor {
+    /// DHT11 on the temperature pin.
+    Dht11,
+    /// SHT3x at address 0x44, or 0x45 with the ADDR pin high.
+    Sht3x { address: u8 },
+    /// SHT4x at address 0x44.
+    Sht4x,
+    /// BME280 at address 0x76, or 0x77 with the SDO pin high.
+    Bme280(Bme280),
+}
+
+impl ClimateSensor {
+    pub async fn read<'a, P, D, I>(
+        &mut self,
+        delay: &mut D,
+        pin: &mut FlexPin<'a, P>,
+        i2c: &mut I,
+    ) -> Result<Reading, ClimateError<I::Error>>
+    where
+        P: Pin,
+        D: Delay,
+        I: I2c,
+    {
+        match self {
+            ClimateSensor::Dht11 => {
+                let reading = dht11::read(delay, pin).map_err(ClimateError::Dht11)?;
+                Ok(Reading {
+                    temperature: reading.temperature,
+                    humidity: reading.relative_humidity,
+                    pressure: None,
+                })
+            }
+            ClimateSensor::Sht3x { address } => sht::read_sht3x(i2c, *address).await,
+            ClimateSensor::Sht4x => sht::read_sht4x(i2c).await,
+            ClimateSensor::Bme280(bme280) => bme280.read(i2c).await,
+        }
+    }
+}
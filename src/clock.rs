@@ -0,0 +1,160 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy::time::Instant;
+
+/// Unix time at which the device booted, or 0 until the clock has been set.
+static BOOTED_AT: AtomicU32 = AtomicU32::new(0);
+
+pub const SECONDS_PER_DAY: u32 = 86400;
+
+/// Set the wall clock from the current Unix time.
+pub fn set(unix_time: u32) {
+    let uptime = Instant::now().as_secs() as u32;
+    BOOTED_AT.store(unix_time.saturating_sub(uptime), Ordering::Relaxed);
+}
+
+/// The current Unix time, if the clock has been set.
+pub fn now() -> Option<u32> {
+    match BOOTED_AT.load(Ordering::Relaxed) {
+        0 => None,
+        booted_at => Some(booted_at + Instant::now().as_secs() as u32),
+    }
+}
+
+/// Seconds since 1970-01-01 in local time, with local time offset from UTC by `utc_offset` seconds.
+pub fn local_time(utc_offset: i32) -> Option<u32> {
+    Some((now()? as i64 + utc_offset as i64) as u32)
+}
+
+/// Seconds since local midnight, with local time offset from UTC by `utc_offset` seconds.
+pub fn time_of_day(utc_offset: i32) -> Option<u32> {
+    let local = now()? as i64 + utc_offset as i64;
+    Some(local.rem_euclid(SECONDS_PER_DAY as i64) as u32)
+}
+
+/// Days since 1970-01-01 in local time, with local time offset from UTC by `utc_offset` seconds.
+pub fn local_day(utc_offset: i32) -> Option<u32> {
+    let local = now()? as i64 + utc_offset as i64;
+    Some(local.div_euclid(SECONDS_PER_DAY as i64) as u32)
+}
+
+/// Parse an HTTP date such as "Tue, 19 Oct 2021 08:12:31 GMT" into Unix time, returning
+/// `None` if it is malformed or out of the range of Unix time in 32 bits.
+pub fn parse_http_date(date: &str) -> Option<u32> {
+    let mut parts = date.split_whitespace().skip(1);
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: u32 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':');
+    let hour: u32 = time.next()?.parse().ok()?;
+    let minute: u32 = time.next()?.parse().ok()?;
+    let second: u32 = time.next()?.parse().ok()?;
+    let date_valid = (1..=31).contains(&day) && (1970..=2106).contains(&year);
+    // Leap seconds are allowed for.
+    let time_valid = hour < 24 && minute < 60 && second <= 60;
+    if !date_valid || !time_valid {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    let time = days * SECONDS_PER_DAY as u64 + (hour * 3600 + minute * 60 + second) as u64;
+    core::convert::TryFrom::try_from(time).ok()
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: u32, month: u32, day: u32) -> u32 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_dates() {
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        // The example from RFC 7231, 7.1.1.1.
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784111777)
+        );
+        assert_eq!(
+            parse_http_date("Tue, 19 Oct 2021 08:12:31 GMT"),
+            Some(1634631151)
+        );
+        assert_eq!(
+            parse_http_date("Sun, 07 Feb 2106 06:28:15 GMT"),
+            Some(u32::MAX)
+        );
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(
+            parse_http_date("Tue, 29 Feb 2000 12:00:00 GMT"),
+            Some(951825600)
+        );
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 23:59:59 GMT"),
+            Some(1709251199)
+        );
+        assert_eq!(
+            parse_http_date("Fri, 01 Mar 2024 00:00:00 GMT"),
+            Some(1709251200)
+        );
+        assert_eq!(
+            parse_http_date("Wed, 01 Mar 2100 00:00:00 GMT"),
+            Some(4107542400)
+        );
+    }
+
+    #[test]
+    fn days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1970, 3, 1), 59);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 12, 31), 20088);
+    }
+
+    #[test]
+    fn bad_dates() {
+        for date in &[
+            "",
+            "garbage",
+            "Tue, 19 Oct 2021",
+            "Tue, 19 Oct 2021 08:12 GMT",
+            "Tue, 19 Okt 2021 08:12:31 GMT",
+            "Tue, 00 Oct 2021 08:12:31 GMT",
+            "Tue, 32 Oct 2021 08:12:31 GMT",
+            "Tue, -1 Oct 2021 08:12:31 GMT",
+            "Tue, 19 Oct 1969 08:12:31 GMT",
+            "Tue, 19 Oct 2200 08:12:31 GMT",
+            "Tue, 19 Oct 2021 24:00:00 GMT",
+            "Tue, 19 Oct 2021 08:60:00 GMT",
+            "Tue, 19 Oct 2021 08:12:61 GMT",
+            "Tue, 19 Oct 2021 8h:12:31 GMT",
+            "Sun, 07 Feb 2106 06:28:16 GMT",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use core::fmt;
+
+/// Formats a value with exactly one decimal place, rounding half away from zero.
+///
+/// This avoids pulling the float formatting machinery of `core` into the firmware, and
+/// gives the exact digit sequence to show on the display: "0.0", "-3.5", "105.0".
+/// Values that are not a number are formatted as "0.0".
+#[derive(Clone, Copy)]
+pub struct Decimal(pub f32);
+
+impl Decimal {
+    /// The value in tenths, rounded half away from zero.
+    fn tenths(&self) -> i64 {
+        let scaled = self.0 * 10.0;
+        if scaled.is_sign_negative() {
+            (scaled - 0.5) as i64
+        } else {
+            (scaled + 0.5) as i64
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tenths = self.tenths();
+        if tenths < 0 {
+            f.write_str("-")?;
+        }
+        let tenths = tenths.unsigned_abs();
+        write!(f, "{}.{}", tenths / 10, tenths % 10)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use heapless::{consts, String};
+
+    fn format(value: f32) -> String<consts::U32> {
+        let mut s = String::new();
+        write!(s, "{}", Decimal(value)).unwrap();
+        s
+    }
+
+    #[test]
+    fn one_decimal_place() {
+        assert_eq!(format(0.0), "0.0");
+        assert_eq!(format(21.0), "21.0");
+        assert_eq!(format(3.14), "3.1");
+        assert_eq!(format(105.0), "105.0");
+    }
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        assert_eq!(format(1.25), "1.3");
+        assert_eq!(format(1.24), "1.2");
+        assert_eq!(format(0.05), "0.1");
+        assert_eq!(format(0.04), "0.0");
+        assert_eq!(format(9.96), "10.0");
+    }
+
+    #[test]
+    fn negatives() {
+        assert_eq!(format(-3.5), "-3.5");
+        assert_eq!(format(-1.25), "-1.3");
+        assert_eq!(format(-0.05), "-0.1");
+        assert_eq!(format(-9.96), "-10.0");
+    }
+
+    #[test]
+    fn no_negative_zero() {
+        assert_eq!(format(-0.0), "0.0");
+        assert_eq!(format(-0.04), "0.0");
+        assert_eq!(format(f32::NAN), "0.0");
+    }
+
+    #[test]
+    fn large_values() {
+        assert_eq!(format(101325.0), "101325.0");
+        assert_eq!(format(1.0e9), "1000000000.0");
+        assert_eq!(format(-1.0e9), "-1000000000.0");
+    }
+}
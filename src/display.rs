@@ -1,36 +1,47 @@
-use super::plant_monitor::Measurement;
+use super::decimal::Decimal;
+use super::icon::Status;
+use super::matrix::{Brightness, Matrix, MatrixCommand, ROW_PERIOD, SENSE_PERIOD};
+use super::plant_monitor::{Event, Measurement};
+use super::rules::Alert;
+use super::stats::{Stat, Summary};
+use super::text::ScrollingText;
+use core::fmt::Write;
 use core::future::Future;
 use core::pin::Pin;
 use drogue_device::{
     actors::{
-        led::matrix::{LEDMatrix, MatrixCommand},
+        led::matrix::fonts::frame_5x5,
         ticker::{Ticker, TickerCommand},
     },
     *,
 };
-use embassy::time::{Duration, Timer};
-use embassy_nrf::gpio::{AnyPin, Output};
-
-type LedMatrix = LEDMatrix<Output<'static, AnyPin>, 5, 5>;
+use embassy::time::{Duration, Instant, Timer};
+use embassy_nrf::gpio::{AnyPin, FlexPin, Output};
+use heapless::{consts, String};
 
 pub struct Display {
-    matrix: ActorContext<'static, LedMatrix>,
-    refresher: ActorContext<'static, Ticker<'static, LedMatrix>>,
+    matrix: ActorContext<'static, Matrix>,
+    refresher: ActorContext<'static, Ticker<'static, Matrix>>,
+    light: ActorContext<'static, Ticker<'static, Matrix>>,
     display: ActorContext<'static, DisplayActor>,
+    expiry: ActorContext<'static, Ticker<'static, DisplayActor>>,
 }
 
 impl Display {
     pub fn new(
         pin_rows: [Output<'static, AnyPin>; 5],
-        pin_cols: [Output<'static, AnyPin>; 5],
+        pin_cols: [FlexPin<'static, AnyPin>; 5],
+        brightness: Brightness,
+        scroll_speed: Duration,
+        mode: DisplayMode,
+        status_duration: Duration,
     ) -> Self {
         Self {
-            matrix: ActorContext::new(LEDMatrix::new(pin_rows, pin_cols)),
-            refresher: ActorContext::new(Ticker::new(
-                Duration::from_millis(1000 / 200),
-                MatrixCommand::Render,
-            )),
-            display: ActorContext::new(DisplayActor::new()),
+            matrix: ActorContext::new(Matrix::new(pin_rows, pin_cols, brightness)),
+            refresher: ActorContext::new(Ticker::new(ROW_PERIOD, MatrixCommand::Render)),
+            light: ActorContext::new(Ticker::new(SENSE_PERIOD, MatrixCommand::SenseLight)),
+            display: ActorContext::new(DisplayActor::new(scroll_speed, mode, status_duration)),
+            expiry: ActorContext::new(Ticker::new(Duration::from_secs(1), DisplayCommand::Tick)),
         }
     }
 }
@@ -47,31 +58,217 @@ impl Package for Display {
     ) -> Address<Self::Primary> {
         let matrix = self.matrix.mount((), spawner);
         let refresher = self.refresher.mount(matrix, spawner);
-        self.display.mount((matrix, refresher), spawner)
+        self.light.mount(matrix, spawner);
+        let display = self.display.mount((matrix, refresher), spawner);
+        self.expiry.mount(display, spawner);
+        display
+    }
+}
+
+/// What the display shows for each measurement.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DisplayMode {
+    Temperature,
+    Humidity,
+    Soil,
+    /// Scroll all the values in sequence.
+    All,
+    /// Fill the grid from the bottom in proportion to the soil moisture.
+    SoilBar,
+}
+
+impl DisplayMode {
+    fn next(self) -> Self {
+        match self {
+            DisplayMode::Temperature => DisplayMode::Humidity,
+            DisplayMode::Humidity => DisplayMode::Soil,
+            DisplayMode::Soil => DisplayMode::All,
+            DisplayMode::All => DisplayMode::SoilBar,
+            DisplayMode::SoilBar => DisplayMode::Temperature,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum DisplayCommand {
+    Show(Measurement),
+    /// Scroll the name of the alert when raised.
+    Alert(Alert),
+    /// Scroll the range of the temperature, humidity and soil moisture over the day.
+    Summary(Summary),
+    /// Scroll the range of the temperature, humidity and soil moisture over the last
+    /// 24 hours.
+    LastDay(Summary),
+    NextMode,
+    /// Show the status icon instead of the blank screen, for a while or until the
+    /// condition is cleared. Statuses of lower priority than the one shown are ignored.
+    Status(Status),
+    /// Stop showing the status icon of a condition that has cleared.
+    ClearStatus(Status),
+    /// Clear the status icon once it has been shown long enough.
+    Tick,
+}
+
+impl From<Event> for DisplayCommand {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::Measurement(measurement) => DisplayCommand::Show(measurement),
+            Event::Alert(alert) => DisplayCommand::Alert(alert),
+            Event::Summary(summary) => DisplayCommand::Summary(summary),
+        }
+    }
+}
+
+/// Format the values shown in the given mode, or `None` if it shows a graph instead.
+fn describe(mode: DisplayMode, measurement: &Measurement) -> Option<String<consts::U32>> {
+    let temperature = Decimal(measurement.temperature);
+    let humidity = Decimal(measurement.humidity);
+    let moisture = measurement.moisture;
+
+    let mut text = String::new();
+    match mode {
+        DisplayMode::Temperature => write!(text, "{}°C", temperature),
+        DisplayMode::Humidity => write!(text, "{}%", humidity),
+        DisplayMode::Soil => write!(text, "SOIL {}%", moisture),
+        DisplayMode::All => write!(text, "{}°C {}% SOIL {}%", temperature, humidity, moisture),
+        DisplayMode::SoilBar => return None,
+    }
+    .unwrap();
+    Some(text)
+}
+
+/// Format the range of the values of the summary after the label, leaving out values
+/// without readings: "DAY 12.5-24.0°C 40-65% SOIL 31-45%".
+fn summarize(label: &str, summary: &Summary) -> String<consts::U64> {
+    let percent = |value: f32| (value + 0.5) as u32;
+    let mut text = String::new();
+    text.push_str(label).unwrap();
+    if let Some(Stat { min, max, .. }) = summary.temperature {
+        write!(text, " {}-{}°C", Decimal(min), Decimal(max)).unwrap();
+    }
+    if let Some(Stat { min, max, .. }) = summary.humidity {
+        write!(text, " {}-{}%", percent(min), percent(max)).unwrap();
     }
+    if let Some(Stat { min, max, .. }) = summary.moisture {
+        write!(text, " SOIL {}-{}%", percent(min), percent(max)).unwrap();
+    }
+    text
 }
 
+/// How long the soil bar graph is shown.
+const BAR_DURATION: Duration = Duration::from_secs(3);
+
 pub struct DisplayActor {
-    matrix: Option<Address<'static, LedMatrix>>,
-    refresher: Option<Address<'static, Ticker<'static, LedMatrix>>>,
+    matrix: Option<Address<'static, Matrix>>,
+    refresher: Option<Address<'static, Ticker<'static, Matrix>>>,
+    scroll_speed: Duration,
+    mode: DisplayMode,
+    last: Option<Measurement>,
+    /// The status shown, and until when unless it is a condition.
+    status: Option<(Status, Option<Instant>)>,
+    status_duration: Duration,
 }
 
 impl DisplayActor {
-    pub fn new() -> Self {
+    /// Create a display scrolling text one column every `scroll_speed`, and keeping
+    /// status icons on the display for `status_duration`.
+    pub fn new(scroll_speed: Duration, mode: DisplayMode, status_duration: Duration) -> Self {
         Self {
             matrix: None,
             refresher: None,
+            scroll_speed,
+            mode,
+            last: None,
+            status: None,
+            status_duration,
+        }
+    }
+
+    /// Show the measurement according to the current mode, returning to idle afterwards.
+    async fn show(&mut self, measurement: Measurement) {
+        self.refresher
+            .unwrap()
+            .request(TickerCommand::Start)
+            .unwrap()
+            .await;
+
+        match describe(self.mode, &measurement) {
+            Some(text) => self.scroll(&text).await,
+            None => self.bar(measurement.moisture).await,
+        }
+
+        self.idle().await;
+    }
+
+    /// Show the current status icon, or blank the display if there is none.
+    async fn idle(&mut self) {
+        if let Some((status, _)) = self.status {
+            self.refresher
+                .unwrap()
+                .request(TickerCommand::Start)
+                .unwrap()
+                .await;
+            self.matrix
+                .unwrap()
+                .request(MatrixCommand::ApplyFrame(&status.icon()))
+                .unwrap()
+                .await;
+            return;
+        }
+
+        self.refresher
+            .unwrap()
+            .request(TickerCommand::Stop)
+            .unwrap()
+            .await;
+
+        self.matrix
+            .unwrap()
+            .request(MatrixCommand::Clear)
+            .unwrap()
+            .await;
+        self.matrix
+            .unwrap()
+            .request(MatrixCommand::Render)
+            .unwrap()
+            .await;
+    }
+
+    /// Scroll the text across the display, returning once it has scrolled out of view.
+    async fn scroll(&mut self, text: &str) {
+        for frame in ScrollingText::new(text) {
+            self.matrix
+                .unwrap()
+                .request(MatrixCommand::ApplyFrame(&frame))
+                .unwrap()
+                .await;
+            Timer::after(self.scroll_speed).await;
         }
     }
+
+    /// Light up one of the 25 pixels for every 4 %, filling rows from the bottom.
+    async fn bar(&mut self, percent: u8) {
+        let lit = (core::cmp::min(percent, 100) as usize * 25 + 50) / 100;
+        let mut rows = [0; 5];
+        for i in 0..lit {
+            rows[4 - i / 5] |= 0b10000 >> (i % 5);
+        }
+        self.matrix
+            .unwrap()
+            .request(MatrixCommand::ApplyFrame(&frame_5x5(&rows)))
+            .unwrap()
+            .await;
+        Timer::after(BAR_DURATION).await;
+    }
 }
 
 impl Actor for DisplayActor {
     type Configuration = (
-        Address<'static, LedMatrix>,
-        Address<'static, Ticker<'static, LedMatrix>>,
+        Address<'static, Matrix>,
+        Address<'static, Ticker<'static, Matrix>>,
     );
 
-    type Message<'m> = Measurement;
+    type Message<'m> = DisplayCommand;
     type OnStartFuture<'m> = impl Future<Output = ()> + 'm;
     type OnMessageFuture<'m> = impl Future<Output = ()> + 'm;
 
@@ -91,64 +288,80 @@ impl Actor for DisplayActor {
         message: Self::Message<'m>,
     ) -> Self::OnMessageFuture<'m> {
         async move {
-            log::trace!("Displaying measurement");
-            self.refresher
-                .unwrap()
-                .request(TickerCommand::Start)
-                .unwrap()
-                .await;
-
-            let mut temp: u8 = if message.temperature.is_sign_negative() {
-                self.matrix
-                    .unwrap()
-                    .request(MatrixCommand::ApplyFrame(&'-'))
-                    .unwrap()
-                    .await;
-                Timer::after(Duration::from_secs(1)).await;
-                (message.temperature * -1.0) as u8
-            } else {
-                message.temperature as u8
-            };
-
-            while temp != 0 {
-                let c = char::from_digit(
-                    if temp < 10 {
-                        let d = temp;
-                        temp = 0;
-                        d
+            let this = unsafe { self.get_unchecked_mut() };
+            match message {
+                DisplayCommand::Show(measurement) => {
+                    log::trace!("Displaying measurement");
+                    this.last.replace(measurement);
+                    this.show(measurement).await;
+                }
+                DisplayCommand::Alert(alert) => {
+                    if alert.active {
+                        this.refresher
+                            .unwrap()
+                            .request(TickerCommand::Start)
+                            .unwrap()
+                            .await;
+                        let mut text: String<consts::U32> = String::new();
+                        write!(text, "{}!", alert.rule).ok();
+                        this.scroll(&text).await;
+                        this.idle().await;
+                    }
+                }
+                DisplayCommand::Summary(summary) | DisplayCommand::LastDay(summary) => {
+                    // The day just over, or the rolling window up to now.
+                    let label = match message {
+                        DisplayCommand::Summary(_) => "DAY",
+                        _ => "24H",
+                    };
+                    this.refresher
+                        .unwrap()
+                        .request(TickerCommand::Start)
+                        .unwrap()
+                        .await;
+                    this.scroll(&summarize(label, &summary)).await;
+                    this.idle().await;
+                }
+                DisplayCommand::NextMode => {
+                    this.mode = this.mode.next();
+                    log::info!("Display mode {:?}", this.mode);
+                    // Show the last measurement right away so the new mode is visible.
+                    if let Some(measurement) = this.last {
+                        this.show(measurement).await;
+                    }
+                }
+                DisplayCommand::Status(status) => {
+                    log::debug!("Status {:?}", status);
+                    if let Some((shown, _)) = this.status {
+                        if shown.priority() > status.priority() {
+                            return;
+                        }
+                    }
+                    let until = if status.is_condition() {
+                        None
                     } else {
-                        let d = temp / 10;
-                        temp %= 10;
-                        d
-                    } as u32,
-                    10,
-                )
-                .unwrap();
-                self.matrix
-                    .unwrap()
-                    .request(MatrixCommand::ApplyFrame(&c))
-                    .unwrap()
-                    .await;
-
-                Timer::after(Duration::from_secs(1)).await;
+                        Some(Instant::now() + this.status_duration)
+                    };
+                    this.status.replace((status, until));
+                    this.idle().await;
+                }
+                DisplayCommand::ClearStatus(status) => {
+                    if let Some((shown, _)) = this.status {
+                        if shown == status {
+                            this.status.take();
+                            this.idle().await;
+                        }
+                    }
+                }
+                DisplayCommand::Tick => {
+                    if let Some((_, Some(until))) = this.status {
+                        if Instant::now() >= until {
+                            this.status.take();
+                            this.idle().await;
+                        }
+                    }
+                }
             }
-
-            self.refresher
-                .unwrap()
-                .request(TickerCommand::Stop)
-                .unwrap()
-                .await;
-
-            self.matrix
-                .unwrap()
-                .request(MatrixCommand::Clear)
-                .unwrap()
-                .await;
-            self.matrix
-                .unwrap()
-                .request(MatrixCommand::Render)
-                .unwrap()
-                .await;
         }
     }
 }
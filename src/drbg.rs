@@ -0,0 +1,230 @@
+use core::num::NonZeroU32;
+use embassy::time::{Duration, Instant};
+use rand_chacha::ChaCha20Rng;
+use rand_core::{CryptoRng, RngCore, SeedableRng};
+
+/// Reported when random bytes are requested before the first seed is ready.
+pub const NOT_SEEDED: u32 = rand_core::Error::CUSTOM_START + 0x100;
+
+/// Source of the time the reseed interval is measured in.
+pub trait TimeSource {
+    fn now(&self) -> Instant;
+}
+
+/// Time since boot, as kept by the executor.
+pub struct Uptime;
+
+impl TimeSource for Uptime {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Source of the seeds the generator is keyed with.
+pub trait Entropy {
+    /// Returns a new seed if one is ready, without waiting for it.
+    fn seed(&mut self) -> Result<Option<[u8; 32]>, rand_core::Error>;
+}
+
+/// Deterministic random bit generator seeded from a slower entropy source.
+///
+/// Produces the output of ChaCha20 keyed with a seed from the source, reseeded after a
+/// number of bytes have been generated or some time has passed, whichever comes first.
+/// The source gathers seeds in the background, so if no new seed is ready when one is
+/// due the current key is used until it is.
+pub struct Drbg<E, T = Uptime>
+where
+    E: Entropy,
+    T: TimeSource,
+{
+    entropy: E,
+    time: T,
+    rng: Option<ChaCha20Rng>,
+    generated: usize,
+    seeded_at: Instant,
+    reseed_bytes: usize,
+    reseed_interval: Duration,
+}
+
+impl<E, T> Drbg<E, T>
+where
+    E: Entropy,
+    T: TimeSource,
+{
+    pub fn new(entropy: E, time: T, reseed_bytes: usize, reseed_interval: Duration) -> Self {
+        let seeded_at = time.now();
+        Self {
+            entropy,
+            time,
+            rng: None,
+            generated: 0,
+            seeded_at,
+            reseed_bytes,
+            reseed_interval,
+        }
+    }
+
+    fn generator(&mut self, len: usize) -> Result<&mut ChaCha20Rng, rand_core::Error> {
+        if self.rng.is_none()
+            || self.generated + len > self.reseed_bytes
+            || self.time.now() - self.seeded_at > self.reseed_interval
+        {
+            match self.entropy.seed()? {
+                Some(seed) => {
+                    self.rng.replace(ChaCha20Rng::from_seed(seed));
+                    self.generated = 0;
+                    self.seeded_at = self.time.now();
+                }
+                None if self.rng.is_some() => {}
+                None => return Err(NonZeroU32::new(NOT_SEEDED).unwrap().into()),
+            }
+        }
+        self.generated += len;
+        Ok(self.rng.as_mut().unwrap())
+    }
+}
+
+impl<E, T> RngCore for Drbg<E, T>
+where
+    E: Entropy,
+    T: TimeSource,
+{
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.fill_bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // Handing out bytes without a seed, or from a failed source, would silently
+        // weaken every key made from them.
+        if let Err(e) = self.try_fill_bytes(dest) {
+            panic!("entropy source failed: {}", e);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        let len = dest.len();
+        self.generator(len)?.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<E, T> CryptoRng for Drbg<E, T>
+where
+    E: Entropy,
+    T: TimeSource,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Hands out seeds filled with 0, 1, 2 and so on, up to the given count.
+    struct Seeds {
+        taken: u8,
+        available: u8,
+    }
+
+    impl Seeds {
+        fn new(available: u8) -> Self {
+            Self {
+                taken: 0,
+                available,
+            }
+        }
+    }
+
+    impl Entropy for &mut Seeds {
+        fn seed(&mut self) -> Result<Option<[u8; 32]>, rand_core::Error> {
+            if self.taken == self.available {
+                return Ok(None);
+            }
+            self.taken += 1;
+            Ok(Some([self.taken - 1; 32]))
+        }
+    }
+
+    impl TimeSource for &Cell<u64> {
+        fn now(&self) -> Instant {
+            Instant::from_secs(self.get())
+        }
+    }
+
+    fn drbg<'a>(seeds: &'a mut Seeds, time: &'a Cell<u64>) -> Drbg<&'a mut Seeds, &'a Cell<u64>> {
+        Drbg::new(seeds, time, 64, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn chacha20_known_answer() {
+        // ChaCha20 block 0 with an all-zero key and nonce (RFC 7539, A.1).
+        let expected = [
+            0x76, 0xb8, 0xe0, 0xad, 0xa0, 0xf1, 0x3d, 0x90, 0x40, 0x5d, 0x6a, 0xe5, 0x53, 0x86,
+            0xbd, 0x28, 0xbd, 0xd2, 0x19, 0xb8, 0xa0, 0x8d, 0xed, 0x1a, 0xa8, 0x36, 0xef, 0xcc,
+            0x8b, 0x77, 0x0d, 0xc7, 0xda, 0x41, 0x59, 0x7c, 0x51, 0x57, 0x48, 0x8d, 0x77, 0x24,
+            0xe0, 0x3f, 0xb8, 0xd8, 0x4a, 0x37, 0x6a, 0x43, 0xb8, 0xf4, 0x15, 0x18, 0xa1, 0x1c,
+            0xc3, 0x87, 0xb6, 0x69, 0xb2, 0xee, 0x65, 0x86,
+        ];
+        let (mut seeds, time) = (Seeds::new(1), Cell::new(0));
+        let mut drbg = drbg(&mut seeds, &time);
+        let mut output = [0; 64];
+        drbg.try_fill_bytes(&mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn not_seeded() {
+        let (mut seeds, time) = (Seeds::new(0), Cell::new(0));
+        let mut drbg = drbg(&mut seeds, &time);
+        let error = drbg.try_fill_bytes(&mut [0; 4]).unwrap_err();
+        assert_eq!(error.code(), NonZeroU32::new(NOT_SEEDED));
+    }
+
+    #[test]
+    fn reseeds_after_bytes() {
+        let (mut seeds, time) = (Seeds::new(3), Cell::new(0));
+        let mut drbg = drbg(&mut seeds, &time);
+        drbg.try_fill_bytes(&mut [0; 32]).unwrap();
+        drbg.try_fill_bytes(&mut [0; 32]).unwrap();
+        drbg.try_fill_bytes(&mut [0; 1]).unwrap();
+        drop(drbg);
+        assert_eq!(seeds.taken, 2);
+    }
+
+    #[test]
+    fn reseeds_after_interval() {
+        let (mut seeds, time) = (Seeds::new(3), Cell::new(0));
+        let mut drbg = drbg(&mut seeds, &time);
+        drbg.try_fill_bytes(&mut [0; 4]).unwrap();
+        time.set(60);
+        drbg.try_fill_bytes(&mut [0; 4]).unwrap();
+        time.set(61);
+        drbg.try_fill_bytes(&mut [0; 4]).unwrap();
+        drop(drbg);
+        assert_eq!(seeds.taken, 2);
+    }
+
+    #[test]
+    fn keeps_key_until_seed_is_ready() {
+        let (mut seeds, time) = (Seeds::new(1), Cell::new(0));
+        let mut drbg = drbg(&mut seeds, &time);
+        let mut first = [0; 64];
+        drbg.try_fill_bytes(&mut first).unwrap();
+        let mut next = [0; 32];
+        drbg.try_fill_bytes(&mut next).unwrap();
+        // The key is kept, so the output continues the same stream.
+        let mut reference = ChaCha20Rng::from_seed([0; 32]);
+        let mut expected = [0; 96];
+        reference.fill_bytes(&mut expected);
+        assert_eq!(&first[..], &expected[..64]);
+        assert_eq!(&next[..], &expected[64..]);
+    }
+}
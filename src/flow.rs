@@ -0,0 +1,144 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embassy_nrf::{
+    gpio::Input,
+    peripherals::{GPIOTE_CH7, P0_12, PPI_CH2},
+};
+use nrf52833_pac::{GPIOTE, PPI, TIMER1};
+
+/// Water delivered since boot in millilitres.
+static DELIVERED: AtomicU32 = AtomicU32::new(0);
+
+/// Whether a flow meter has been set up.
+static FITTED: AtomicBool = AtomicBool::new(false);
+
+/// Water delivered to the plant since boot in millilitres, if a flow meter is fitted.
+pub fn delivered() -> Option<u32> {
+    if FITTED.load(Ordering::Relaxed) {
+        Some(DELIVERED.load(Ordering::Relaxed))
+    } else {
+        None
+    }
+}
+
+/// Counts the pulses of a hall effect flow sensor in hardware.
+///
+/// Each pulse raises a GPIOTE event that PPI connects to the count task of a timer in
+/// counter mode, so no pulses are lost whatever the CPU is doing.
+pub struct FlowMeter {
+    timer: TIMER1,
+    _pin: Input<'static, P0_12>,
+    _channel: GPIOTE_CH7,
+    _ppi: PPI_CH2,
+    volume: Volume,
+}
+
+impl FlowMeter {
+    pub fn new(
+        timer: TIMER1,
+        gpiote: &GPIOTE,
+        ppi: &PPI,
+        pin: Input<'static, P0_12>,
+        channel: GPIOTE_CH7,
+        ppi_channel: PPI_CH2,
+        pulses_per_litre: u32,
+    ) -> Self {
+        gpiote.config[7]
+            .write(|w| unsafe { w.mode().event().psel().bits(12).polarity().hi_to_lo() });
+
+        timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+        timer.mode.write(|w| w.mode().counter());
+        timer.bitmode.write(|w| w.bitmode()._32bit());
+        timer.tasks_clear.write(|w| unsafe { w.bits(1) });
+        timer.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        ppi.ch[2]
+            .eep
+            .write(|w| unsafe { w.bits(&gpiote.events_in[7] as *const _ as u32) });
+        ppi.ch[2]
+            .tep
+            .write(|w| unsafe { w.bits(&timer.tasks_count as *const _ as u32) });
+        ppi.chenset.write(|w| unsafe { w.bits(1 << 2) });
+        FITTED.store(true, Ordering::Relaxed);
+
+        Self {
+            timer,
+            _pin: pin,
+            _channel: channel,
+            _ppi: ppi_channel,
+            volume: Volume::new(pulses_per_litre),
+        }
+    }
+
+    fn pulses(&self) -> u32 {
+        self.timer.tasks_capture[0].write(|w| unsafe { w.bits(1) });
+        self.timer.cc[0].read().bits()
+    }
+
+    /// Water that flowed since the last call in millilitres, adding it to the water
+    /// delivered.
+    pub fn take(&mut self) -> u32 {
+        let ml = self.volume.take(self.pulses());
+        DELIVERED.fetch_add(ml, Ordering::Relaxed);
+        ml
+    }
+}
+
+/// Converts the pulse counter into millilitres.
+///
+/// Works from the total of pulses counted, so the fractions of a millilitre left over
+/// each time add up over many small amounts instead of being lost or counted twice.
+struct Volume {
+    pulses_per_litre: u32,
+    /// Counter value at the last call, it wraps around after 2^32 pulses.
+    counter: u32,
+    pulses: u64,
+    reported_ml: u64,
+}
+
+impl Volume {
+    fn new(pulses_per_litre: u32) -> Self {
+        Self {
+            pulses_per_litre,
+            counter: 0,
+            pulses: 0,
+            reported_ml: 0,
+        }
+    }
+
+    /// Millilitres flowed since the last call, given the current counter value.
+    fn take(&mut self, counter: u32) -> u32 {
+        self.pulses += counter.wrapping_sub(self.counter) as u64;
+        self.counter = counter;
+        let ml = self.pulses * 1000 / self.pulses_per_litre as u64;
+        let new = ml - self.reported_ml;
+        self.reported_ml = ml;
+        new as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_new_pulses() {
+        let mut volume = Volume::new(450);
+        assert_eq!(volume.take(1), 2);
+        assert_eq!(volume.take(1), 0);
+        assert_eq!(volume.take(1), 0);
+    }
+
+    #[test]
+    fn fractions_add_up() {
+        let mut volume = Volume::new(450);
+        let total: u32 = (1..=450).map(|counter| volume.take(counter)).sum();
+        assert_eq!(total, 1000);
+    }
+
+    #[test]
+    fn counter_wraps_around() {
+        let mut volume = Volume::new(1000);
+        assert_eq!(volume.take(u32::MAX - 9), 4_294_967_286);
+        assert_eq!(volume.take(10), 20);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::plant_monitor::{DeviceId, Event, Measurement, SoilCalibration};
+use core::future::Future;
+use core::pin::Pin;
+use drogue_device::*;
+use heapless::{consts, Vec};
+use nrf_softdevice::{ble::central, raw, Softdevice};
+use rand_core::{CryptoRng, RngCore};
+
+/// Service UUID d2745a64-81e0-485f-84e9-987c491c5a33 identifying planteboks beacons,
+/// in the little endian order it is advertised in.
+const SERVICE_UUID: [u8; 16] = [
+    0x33, 0x5a, 0x1c, 0x49, 0x7c, 0x98, 0xe9, 0x84, 0x5f, 0x48, 0xe0, 0x81, 0x64, 0x5a, 0x74, 0xd2,
+];
+const PROTOCOL_VERSION: u8 = 1;
+/// Length of the service data following the uuid.
+const PAYLOAD_LEN: usize = 9;
+
+const AD_COMPLETE_LOCAL_NAME: u8 = 0x09;
+const AD_SERVICE_DATA_128: u8 = 0x21;
+
+/// Readings broadcast by a pot that is out of Wi-Fi range.
+///
+/// The service data is laid out as follows (little endian):
+///
+/// | offset | field                         |
+/// |--------|-------------------------------|
+/// | 0      | service uuid                  |
+/// | 16     | protocol version              |
+/// | 17     | sequence number               |
+/// | 19     | soil sample                   |
+/// | 21     | temperature in 0.1 °C         |
+/// | 23     | relative humidity in 0.1 %    |
+///
+/// The device identity is the Bluetooth address. The service data takes 27 of
+/// the 31 bytes of a legacy advertisement and scan responses are not requested,
+/// so a complete local name is only used when it is two characters or shorter.
+/// Beacons with a name that is not a valid [`DeviceId`] are dropped.
+#[derive(Clone, Copy)]
+pub struct Beacon {
+    pub device: DeviceId,
+    pub sequence: u16,
+    pub soil: i16,
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+impl Beacon {
+    /// Parse a beacon from the raw advertising data, returning `None` if
+    /// the advertisement was not sent by a planteboks.
+    pub fn parse(address: &[u8; 6], data: &[u8]) -> Option<Beacon> {
+        let mut name = None;
+        let mut payload = None;
+
+        let mut data = data;
+        while data.len() >= 2 {
+            let len = data[0] as usize;
+            if len == 0 || len + 1 > data.len() {
+                break;
+            }
+            let (kind, value) = (data[1], &data[2..len + 1]);
+            match kind {
+                AD_COMPLETE_LOCAL_NAME => name = core::str::from_utf8(value).ok(),
+                AD_SERVICE_DATA_128 if value.starts_with(&SERVICE_UUID) => {
+                    payload = Some(&value[SERVICE_UUID.len()..])
+                }
+                _ => {}
+            }
+            data = &data[len + 1..];
+        }
+
+        let payload = payload?;
+        if payload.len() < PAYLOAD_LEN || payload[0] != PROTOCOL_VERSION {
+            return None;
+        }
+
+        let device = match name {
+            Some(name) => DeviceId::new(name)?,
+            None => DeviceId::from_address(address),
+        };
+
+        Some(Beacon {
+            device,
+            sequence: u16::from_le_bytes([payload[1], payload[2]]),
+            soil: i16::from_le_bytes([payload[3], payload[4]]),
+            temperature: i16::from_le_bytes([payload[5], payload[6]]) as f32 / 10.0,
+            humidity: u16::from_le_bytes([payload[7], payload[8]]) as f32 / 10.0,
+        })
+    }
+}
+
+impl From<Beacon> for Measurement {
+    fn from(beacon: Beacon) -> Measurement {
+        Measurement {
+            soil: beacon.soil,
+            moisture: SoilCalibration::default().moisture(beacon.soil),
+            temperature: beacon.temperature,
+            humidity: beacon.humidity,
+            pressure: None,
+            temperature_fallback: false,
+            board_temperature: None,
+            dew_point: None,
+            absolute_humidity: None,
+            vpd: None,
+            soil_temperature: None,
+            lux: None,
+            dli: None,
+            water: None,
+            device: Some(beacon.device),
+        }
+    }
+}
+
+/// Forwards beacons from other pots through the network endpoint, on
+/// behalf of the device that sent them.
+///
+/// Pots advertise the same reading several times, so only beacons with a
+/// sequence number newer than the last forwarded one are passed on.
+#[rustfmt::skip]
+pub struct Gateway<A>
+where
+    A: Actor<Message<'static> = Event> + 'static,
+{
+    seen: Seen,
+    network: Option<Address<'static, A>>,
+}
+
+/// Sequence numbers going back by more than this are taken to come from a pot that
+/// restarted and counts from zero again, rather than from a repeated advertisement.
+const RESTART_JUMP: i16 = 64;
+
+/// The last sequence number forwarded for each pot heard recently.
+struct Seen {
+    pots: Vec<(DeviceId, u16), consts::U16>,
+}
+
+impl Seen {
+    fn new() -> Self {
+        Self { pots: Vec::new() }
+    }
+
+    /// Record the beacon sequence number, returning false if it has already been seen.
+    fn is_new(&mut self, beacon: &Beacon) -> bool {
+        if let Some((_, last)) = self.pots.iter_mut().find(|(d, _)| *d == beacon.device) {
+            // Sequence numbers wrap around, so compare using the distance between them.
+            let distance = beacon.sequence.wrapping_sub(*last) as i16;
+            if distance <= 0 && distance > -RESTART_JUMP {
+                return false;
+            }
+            *last = beacon.sequence;
+        } else {
+            // Forget the oldest pots first, they are the likeliest to have gone quiet.
+            if self.pots.len() == self.pots.capacity() {
+                self.pots.rotate_left(1);
+                self.pots.pop();
+            }
+            self.pots.push((beacon.device, beacon.sequence)).ok();
+        }
+        true
+    }
+}
+
+#[rustfmt::skip]
+impl<A> Gateway<A>
+where
+    A: Actor<Message<'static> = Event> + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            seen: Seen::new(),
+            network: None,
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl<A> Actor for Gateway<A>
+where
+    A: Actor<Message<'static> = Event> + 'static,
+{
+    type Configuration = Address<'static, A>;
+
+    type Message<'m> = Beacon;
+    type OnStartFuture<'m> = impl Future<Output = ()> + 'm;
+    type OnMessageFuture<'m> = impl Future<Output = ()> + 'm;
+
+    fn on_mount(&mut self, _: Address<'static, Self>, config: Self::Configuration) {
+        self.network.replace(config);
+    }
+
+    fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
+        async move {}
+    }
+
+    fn on_message<'m>(
+        self: Pin<&'m mut Self>,
+        message: Self::Message<'m>,
+    ) -> Self::OnMessageFuture<'m> {
+        async move {
+            let this = unsafe { self.get_unchecked_mut() };
+            if this.seen.is_new(&message) {
+                log::info!(
+                    "Forwarding beacon {} from {}",
+                    message.sequence,
+                    message.device.as_str()
+                );
+                this.network
+                    .unwrap()
+                    .request(Event::Measurement(message.into()))
+                    .unwrap()
+                    .await;
+            }
+        }
+    }
+}
+
+/// Continuously scan for planteboks beacons and hand them to the gateway.
+#[embassy::task]
+pub async fn scan_task(
+    sd: &'static Softdevice,
+    gateway: Address<'static, Gateway<crate::Network>>,
+) {
+    let config = central::ScanConfig::default();
+    loop {
+        let result = central::scan(
+            sd,
+            &config,
+            |params: &raw::ble_gap_evt_adv_report_t| unsafe {
+                let data =
+                    core::slice::from_raw_parts(params.data.p_data, params.data.len as usize);
+                Beacon::parse(&params.peer_addr.addr, data)
+            },
+        )
+        .await;
+        match result {
+            Ok(beacon) => {
+                if let Err(e) = gateway.notify(beacon) {
+                    log::warn!("Error forwarding beacon: {:?}", e);
+                }
+            }
+            Err(e) => log::warn!("Error scanning for beacons: {:?}", e),
+        }
+    }
+}
+
+#[embassy::task]
+pub async fn softdevice_task(sd: &'static Softdevice) {
+    sd.run().await;
+}
+
+/// Enable the SoftDevice with a single central role for scanning.
+pub fn enable_softdevice() -> &'static Softdevice {
+    let config = nrf_softdevice::Config {
+        clock: Some(raw::nrf_clock_lf_cfg_t {
+            source: raw::NRF_CLOCK_LF_SRC_RC as u8,
+            rc_ctiv: 4,
+            rc_temp_ctiv: 2,
+            accuracy: 7,
+        }),
+        gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
+            adv_set_count: 0,
+            periph_role_count: 0,
+            central_role_count: 1,
+            central_sec_count: 0,
+            _bitfield_1: Default::default(),
+        }),
+        ..Default::default()
+    };
+    Softdevice::enable(&config)
+}
+
+/// The RNG peripheral is owned by the SoftDevice while it is enabled, so
+/// random bytes for TLS must be requested through it.
+pub struct SoftdeviceRng(&'static Softdevice);
+
+impl SoftdeviceRng {
+    pub fn new(sd: &'static Softdevice) -> Self {
+        Self(sd)
+    }
+}
+
+impl RngCore for SoftdeviceRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.fill_bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // The SoftDevice pool may be temporarily drained, retry until it refills.
+        while nrf_softdevice::random_bytes(self.0, dest).is_err() {}
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for SoftdeviceRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_near;
+
+    const ADDRESS: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6];
+
+    /// Service data AD for a reading of soil 512, 21.5 °C and 40.2 %.
+    fn service_data(sequence: u16) -> [u8; 27] {
+        let mut ad = [0; 27];
+        ad[0] = 26;
+        ad[1] = AD_SERVICE_DATA_128;
+        ad[2..18].copy_from_slice(&SERVICE_UUID);
+        ad[18] = PROTOCOL_VERSION;
+        ad[19..21].copy_from_slice(&sequence.to_le_bytes());
+        ad[21..23].copy_from_slice(&512i16.to_le_bytes());
+        ad[23..25].copy_from_slice(&215i16.to_le_bytes());
+        ad[25..27].copy_from_slice(&402u16.to_le_bytes());
+        ad
+    }
+
+    fn beacon(sequence: u16) -> Beacon {
+        Beacon::parse(&ADDRESS, &service_data(sequence)).unwrap()
+    }
+
+    #[test]
+    fn parse_reading() {
+        let mut data = [0; 30];
+        data[..3].copy_from_slice(&[0x02, 0x01, 0x06]);
+        data[3..].copy_from_slice(&service_data(0x1234));
+        let beacon = Beacon::parse(&ADDRESS, &data).unwrap();
+        assert_eq!(beacon.device.as_str(), "c60504030201");
+        assert_eq!(beacon.sequence, 0x1234);
+        assert_eq!(beacon.soil, 512);
+        assert_near(beacon.temperature, 21.5, 0.01);
+        assert_near(beacon.humidity, 40.2, 0.01);
+    }
+
+    #[test]
+    fn parse_name() {
+        let mut data = [0; 31];
+        data[..27].copy_from_slice(&service_data(1));
+        data[27..].copy_from_slice(&[0x03, AD_COMPLETE_LOCAL_NAME, b'p', b'1']);
+        let beacon = Beacon::parse(&ADDRESS, &data).unwrap();
+        assert_eq!(beacon.device.as_str(), "p1");
+
+        data[29] = b'/';
+        assert!(Beacon::parse(&ADDRESS, &data).is_none());
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let data = service_data(1);
+        for len in 0..data.len() {
+            assert!(Beacon::parse(&ADDRESS, &data[..len]).is_none());
+        }
+        // The AD claims more bytes than there are.
+        let mut data = service_data(1);
+        data[0] = 27;
+        assert!(Beacon::parse(&ADDRESS, &data).is_none());
+    }
+
+    #[test]
+    fn parse_short_payload() {
+        let mut data = service_data(1);
+        data[0] = 25;
+        assert!(Beacon::parse(&ADDRESS, &data[..26]).is_none());
+    }
+
+    #[test]
+    fn parse_foreign() {
+        let mut data = service_data(1);
+        data[2] ^= 0xFF;
+        assert!(Beacon::parse(&ADDRESS, &data).is_none());
+
+        let mut data = service_data(1);
+        data[18] = PROTOCOL_VERSION + 1;
+        assert!(Beacon::parse(&ADDRESS, &data).is_none());
+
+        // Manufacturer specific data carrying the same bytes.
+        let mut data = service_data(1);
+        data[1] = 0xFF;
+        assert!(Beacon::parse(&ADDRESS, &data).is_none());
+
+        assert!(Beacon::parse(&ADDRESS, &[0x00, 0x21, 0x33]).is_none());
+        assert!(Beacon::parse(&ADDRESS, &[0xFF; 31]).is_none());
+    }
+
+    #[test]
+    fn repeated_beacons() {
+        let mut seen = Seen::new();
+        assert!(seen.is_new(&beacon(10)));
+        assert!(!seen.is_new(&beacon(10)));
+        assert!(!seen.is_new(&beacon(9)));
+        assert!(seen.is_new(&beacon(11)));
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut seen = Seen::new();
+        assert!(seen.is_new(&beacon(u16::MAX)));
+        assert!(seen.is_new(&beacon(0)));
+        assert!(!seen.is_new(&beacon(u16::MAX)));
+    }
+
+    #[test]
+    fn restarted_pot() {
+        let mut seen = Seen::new();
+        assert!(seen.is_new(&beacon(5000)));
+        assert!(seen.is_new(&beacon(0)));
+        assert!(!seen.is_new(&beacon(0)));
+        assert!(seen.is_new(&beacon(1)));
+    }
+
+    #[test]
+    fn oldest_pot_forgotten() {
+        let mut seen = Seen::new();
+        for i in 0..=16u8 {
+            let mut beacon = beacon(1);
+            beacon.device = DeviceId::from_address(&[i, 0, 0, 0, 0, 0]);
+            assert!(seen.is_new(&beacon));
+        }
+        let mut first = beacon(1);
+        first.device = DeviceId::from_address(&[0; 6]);
+        assert!(seen.is_new(&first));
+        let mut last = beacon(1);
+        last.device = DeviceId::from_address(&[16, 0, 0, 0, 0, 0]);
+        assert!(!seen.is_new(&last));
+    }
+}
@@ -0,0 +1,204 @@
+/// Gestures made with the two buttons.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gesture {
+    ShortA,
+    LongA,
+    ShortB,
+    LongB,
+    /// Both buttons pressed together.
+    ShortAB,
+    LongAB,
+}
+
+/// Recognizes gestures from the state of the buttons over time.
+///
+/// A gesture starts when the first button is pressed and ends when no buttons are
+/// pressed anymore. Pressing the other button in the meantime makes it a combined
+/// gesture, and holding the buttons longer than the long press duration a long one.
+///
+/// Presses shorter than the debounce time are taken for noise, and presses right after
+/// a gesture ended for the contacts bouncing on release.
+pub struct GestureDetector {
+    long_press_ms: u64,
+    debounce_ms: u64,
+    pressed_at: Option<u64>,
+    released_at: Option<u64>,
+    a: bool,
+    b: bool,
+}
+
+impl GestureDetector {
+    pub const fn new(long_press_ms: u64, debounce_ms: u64) -> Self {
+        Self {
+            long_press_ms,
+            debounce_ms,
+            pressed_at: None,
+            released_at: None,
+            a: false,
+            b: false,
+        }
+    }
+
+    /// Update with the current state of the buttons, returning the gesture once all
+    /// buttons are released.
+    pub fn update(&mut self, a: bool, b: bool, now_ms: u64) -> Option<Gesture> {
+        let bouncing = match self.released_at {
+            Some(released_at) => now_ms.saturating_sub(released_at) < self.debounce_ms,
+            None => false,
+        };
+        let pressed_at = match self.pressed_at {
+            None if (a || b) && !bouncing => {
+                self.pressed_at.replace(now_ms);
+                self.a = a;
+                self.b = b;
+                return None;
+            }
+            None => return None,
+            Some(pressed_at) => pressed_at,
+        };
+
+        self.a |= a;
+        self.b |= b;
+        if a || b {
+            return None;
+        }
+
+        self.pressed_at.take();
+        self.released_at.replace(now_ms);
+        let held = now_ms.saturating_sub(pressed_at);
+        if held < self.debounce_ms {
+            return None;
+        }
+        let long = held >= self.long_press_ms;
+        Some(match (self.a, self.b, long) {
+            (true, true, false) => Gesture::ShortAB,
+            (true, true, true) => Gesture::LongAB,
+            (true, false, false) => Gesture::ShortA,
+            (true, false, true) => Gesture::LongA,
+            (false, _, false) => Gesture::ShortB,
+            (false, _, true) => Gesture::LongB,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG_PRESS: u64 = 2000;
+    const DEBOUNCE: u64 = 20;
+
+    /// Feed the button states at the given times, returning the gestures recognized.
+    fn gestures(states: &[(u64, bool, bool)]) -> [Option<Gesture>; 8] {
+        let mut detector = GestureDetector::new(LONG_PRESS, DEBOUNCE);
+        let mut gestures = [None; 8];
+        let mut n = 0;
+        for (now, a, b) in states.iter() {
+            if let Some(gesture) = detector.update(*a, *b, *now) {
+                gestures[n] = Some(gesture);
+                n += 1;
+            }
+        }
+        gestures
+    }
+
+    fn only(gesture: Gesture) -> [Option<Gesture>; 8] {
+        let mut gestures = [None; 8];
+        gestures[0] = Some(gesture);
+        gestures
+    }
+
+    #[test]
+    fn short_and_long_presses() {
+        let a = |held: u64| gestures(&[(1000, true, false), (1000 + held, false, false)]);
+        let b = |held: u64| gestures(&[(1000, false, true), (1000 + held, false, false)]);
+        assert_eq!(a(100), only(Gesture::ShortA));
+        assert_eq!(a(1999), only(Gesture::ShortA));
+        assert_eq!(a(2000), only(Gesture::LongA));
+        assert_eq!(b(100), only(Gesture::ShortB));
+        assert_eq!(b(3000), only(Gesture::LongB));
+    }
+
+    #[test]
+    fn combined_presses() {
+        let short = [
+            (1000, true, false),
+            (1050, true, true),
+            (1200, false, false),
+        ];
+        assert_eq!(gestures(&short), only(Gesture::ShortAB));
+        let long = [
+            (1000, false, true),
+            (1050, true, true),
+            (3500, false, false),
+        ];
+        assert_eq!(gestures(&long), only(Gesture::LongAB));
+    }
+
+    #[test]
+    fn release_order() {
+        // The gesture ends with the last button released, whichever it is.
+        let a_first = [
+            (1000, true, true),
+            (1100, false, true),
+            (1200, false, false),
+        ];
+        let b_first = [
+            (1000, true, true),
+            (1100, true, false),
+            (1200, false, false),
+        ];
+        assert_eq!(gestures(&a_first), only(Gesture::ShortAB));
+        assert_eq!(gestures(&b_first), only(Gesture::ShortAB));
+        // Pressing and releasing the other button while holding one combines them.
+        let tap_b = [
+            (1000, true, false),
+            (1100, true, true),
+            (1200, true, false),
+            (1300, false, false),
+        ];
+        assert_eq!(gestures(&tap_b), only(Gesture::ShortAB));
+        // Held long until the last release.
+        let long = [
+            (1000, true, true),
+            (1100, false, true),
+            (3100, false, false),
+        ];
+        assert_eq!(gestures(&long), only(Gesture::LongAB));
+    }
+
+    #[test]
+    fn debounce() {
+        // Blips shorter than the debounce time are ignored.
+        assert_eq!(
+            gestures(&[(1000, true, false), (1010, false, false)]),
+            [None; 8]
+        );
+        // Contacts bouncing right after release don't start another gesture.
+        let bounce = [
+            (1000, true, false),
+            (1100, false, false),
+            (1105, true, false),
+            (1110, false, false),
+        ];
+        assert_eq!(gestures(&bounce), only(Gesture::ShortA));
+        // Repeated states within a press make a single gesture.
+        let repeated = [
+            (1000, true, false),
+            (1050, true, false),
+            (1100, false, false),
+            (1150, false, false),
+        ];
+        assert_eq!(gestures(&repeated), only(Gesture::ShortA));
+        // Presses after the debounce time are gestures of their own.
+        let twice = [
+            (1000, true, false),
+            (1100, false, false),
+            (1200, false, true),
+            (1300, false, false),
+        ];
+        let mut expected = only(Gesture::ShortA);
+        expected[1] = Some(Gesture::ShortB);
+        assert_eq!(gestures(&twice), expected);
+    }
+}
@@ -1,93 +1,539 @@
+use crate::clock;
+use crate::trust::{PinnedKey, TrustError};
 use core::fmt::Write;
-use drogue_device::{
-    actors::wifi::{Adapter, Socket},
-    traits::ip::{IpAddress, IpProtocol, SocketAddress},
+use drogue_device::traits::{
+    ip::{IpAddress, IpProtocol, SocketAddress},
+    tcp::{TcpError, TcpSocket},
 };
 use heapless::{consts, String};
 
-pub struct HttpClient<'a, A>
+/// How the device authenticates to the server.
+#[derive(Clone, Copy)]
+pub enum Credentials {
+    Basic {
+        username: &'static str,
+        password: &'static str,
+    },
+    /// The device is authenticated by the client certificate presented during the TLS
+    /// handshake, so no credentials are sent with the request.
+    ClientCertificate,
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    Connect(TcpError),
+    /// The TLS handshake was aborted because the server could not be verified.
+    Tls(TrustError),
+    Write(TcpError),
+    /// The connection failed before any of the response arrived.
+    NoResponse(TcpError),
+    Read(TcpError),
+    InvalidResponse,
+    Status(u16),
+}
+
+/// HTTP client keeping its connection open between requests.
+///
+/// Setting up the TLS session is by far the most expensive part of reporting a
+/// measurement, so the connection is kept alive and reused for later requests. If the
+/// server has closed it in the meantime, the request is retried once on a new
+/// connection. Requests are only retried when sending them failed or nothing came
+/// back, as the server may have acted on a request whose response was cut short.
+pub struct HttpClient<S>
 where
-    A: Adapter + 'static,
+    S: TcpSocket + 'static,
 {
-    socket: Socket<'a, A>,
+    socket: S,
+    connected: bool,
     ip: IpAddress,
     port: u16,
-    username: &'a str,
-    password: &'a str,
+    host: &'static str,
+    credentials: Credentials,
+    verifier: Option<&'static PinnedKey>,
 }
 
-impl<'a, A> HttpClient<'a, A>
+impl<S> HttpClient<S>
 where
-    A: Adapter + 'static,
+    S: TcpSocket + 'static,
 {
     pub fn new(
-        socket: Socket<'a, A>,
+        socket: S,
         ip: IpAddress,
         port: u16,
-        username: &'a str,
-        password: &'a str,
+        host: &'static str,
+        credentials: Credentials,
+        verifier: Option<&'static PinnedKey>,
     ) -> Self {
         Self {
             socket,
+            connected: false,
             ip,
             port,
-            username,
-            password,
+            host,
+            credentials,
+            verifier,
+        }
+    }
+
+    /// Post the payload to the given path, returning the number of bytes of the response
+    /// body written to rx_buf.
+    pub async fn post(
+        &mut self,
+        path: &str,
+        payload: &[u8],
+        content_type: &str,
+        rx_buf: &mut [u8],
+    ) -> Result<usize, HttpError> {
+        let reused = self.connected;
+        match self.request(path, payload, content_type, rx_buf).await {
+            Err(HttpError::Write(_)) | Err(HttpError::NoResponse(_)) if reused => {
+                log::info!("Connection closed by server, reconnecting");
+                self.request(path, payload, content_type, rx_buf).await
+            }
+            result => result,
         }
     }
 
-    pub async fn post(&mut self, path: &str, payload: &[u8]) -> Result<(), ()> {
-        log::info!("Connecting to {}:{}", self.ip, self.port);
-        let result = self
-            .socket
-            .connect(IpProtocol::Tcp, SocketAddress::new(self.ip, self.port))
-            .await;
+    async fn request(
+        &mut self,
+        path: &str,
+        payload: &[u8],
+        content_type: &str,
+        rx_buf: &mut [u8],
+    ) -> Result<usize, HttpError> {
+        if !self.connected {
+            log::info!("Connecting to {}:{}", self.ip, self.port);
+            let verifier = self.verifier;
+            self.socket
+                .connect(IpProtocol::Tcp, SocketAddress::new(self.ip, self.port))
+                .await
+                .map_err(|e| match verifier.and_then(PinnedKey::take_error) {
+                    Some(e) => HttpError::Tls(e),
+                    None => HttpError::Connect(e),
+                })?;
+            self.connected = true;
+        }
 
+        let result = self.exchange(path, payload, content_type, rx_buf).await;
         match result {
-            Ok(_) => {
-                let mut combined: String<consts::U128> = String::new();
-                write!(combined, "{}:{}", self.username, self.password).unwrap();
-                let mut authz = [0; 256];
-                let authz_len =
-                    base64::encode_config_slice(combined.as_bytes(), base64::STANDARD, &mut authz);
-                let mut request: String<consts::U1024> = String::new();
-                write!(request, "POST {} HTTP/1.1\r\n", path).unwrap();
-                write!(request, "Authorization: Basic {}\r\n", unsafe {
-                    core::str::from_utf8_unchecked(&authz[..authz_len])
-                })
-                .unwrap();
-                write!(request, "Content-Type: application/json\r\n").unwrap();
-                write!(request, "Content-Length: {}\r\n\r\n", payload.len()).unwrap();
-
-                log::info!(
-                    "Connected to {:?}. Sending request header of {} bytes",
-                    self.ip,
-                    request.len()
-                );
-                let result = self.socket.send(&request.as_bytes()[..request.len()]).await;
-                match result {
-                    Ok(_) => {
-                        log::info!("Header sent. Sending payload of {} bytes", payload.len());
-                        let result = self.socket.send(payload).await;
-                        match result {
-                            Ok(_) => {
-                                log::info!("Measurement reported");
-                            }
-                            Err(e) => {
-                                log::warn!("Error reporting measurement: {:?}", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::warn!("Error sending headers: {:?}", e);
+            Ok((_, true)) | Err(_) => self.close().await,
+            _ => {}
+        }
+        result.map(|(len, _)| len)
+    }
+
+    async fn close(&mut self) {
+        self.socket.close().await;
+        self.connected = false;
+    }
+
+    /// Send the request and read the response, returning the body length and whether
+    /// the server wants the connection closed.
+    async fn exchange(
+        &mut self,
+        path: &str,
+        payload: &[u8],
+        content_type: &str,
+        rx_buf: &mut [u8],
+    ) -> Result<(usize, bool), HttpError> {
+        let mut request: String<consts::U1024> = String::new();
+        write!(request, "POST {} HTTP/1.1\r\n", path).unwrap();
+        write!(request, "Host: {}\r\n", self.host).unwrap();
+        if let Credentials::Basic { username, password } = self.credentials {
+            let mut combined: String<consts::U128> = String::new();
+            write!(combined, "{}:{}", username, password).unwrap();
+            let mut authz = [0; 256];
+            let authz_len =
+                base64::encode_config_slice(combined.as_bytes(), base64::STANDARD, &mut authz);
+            write!(request, "Authorization: Basic {}\r\n", unsafe {
+                core::str::from_utf8_unchecked(&authz[..authz_len])
+            })
+            .unwrap();
+        }
+        write!(request, "Connection: keep-alive\r\n").unwrap();
+        write!(request, "Content-Type: {}\r\n", content_type).unwrap();
+        write!(request, "Content-Length: {}\r\n\r\n", payload.len()).unwrap();
+
+        log::debug!("Sending request header of {} bytes", request.len());
+        self.write_all(request.as_bytes()).await?;
+        log::debug!("Header sent. Sending payload of {} bytes", payload.len());
+        self.write_all(payload).await?;
+
+        // Read until the end of the response header, which must fit in the buffer.
+        let mut header = [0; 512];
+        let mut pos = 0;
+        let end = loop {
+            if pos == header.len() {
+                return Err(HttpError::InvalidResponse);
+            }
+            let error = if pos == 0 {
+                HttpError::NoResponse
+            } else {
+                HttpError::Read
+            };
+            let n = self.socket.read(&mut header[pos..]).await.map_err(error)?;
+            if n == 0 {
+                return Err(error(TcpError::ReadError));
+            }
+            pos += n;
+            if let Some(end) = find(&header[..pos], b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+
+        let response = Response::parse(&header[..end]).ok_or(HttpError::InvalidResponse)?;
+        // The device has no other source of wall clock time.
+        if let Some(date) = response.date {
+            clock::set(date);
+        }
+
+        // Drain the body so the connection can be reused, keeping what fits in rx_buf.
+        let mut body = Body::new(response.framing, rx_buf);
+        body.feed(&header[end..pos])?;
+        while !body.is_complete() {
+            let n = self
+                .socket
+                .read(&mut header[..])
+                .await
+                .map_err(HttpError::Read)?;
+            if n == 0 {
+                if response.framing == Framing::Close {
+                    break;
+                }
+                return Err(HttpError::Read(TcpError::ReadError));
+            }
+            body.feed(&header[..n])?;
+        }
+        let len = body.len;
+        let close = response.close || response.framing == Framing::Close;
+
+        if (200..300).contains(&response.status) {
+            Ok((len, close))
+        } else {
+            Err(HttpError::Status(response.status))
+        }
+    }
+
+    async fn write_all(&mut self, mut data: &[u8]) -> Result<(), HttpError> {
+        while !data.is_empty() {
+            let n = self.socket.write(data).await.map_err(HttpError::Write)?;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+}
+
+/// How the end of a response body is found.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Framing {
+    Length(usize),
+    Chunked,
+    /// The body runs until the server closes the connection.
+    Close,
+}
+
+struct Response {
+    status: u16,
+    framing: Framing,
+    close: bool,
+    date: Option<u32>,
+}
+
+impl Response {
+    fn parse(header: &[u8]) -> Option<Response> {
+        let header = core::str::from_utf8(header).ok()?;
+        let mut lines = header.split("\r\n");
+        let mut status_line = lines.next()?.split(' ');
+        let version = status_line.next()?;
+        let status = status_line.next()?.parse().ok()?;
+
+        let mut content_length = None;
+        let mut transfer_encoding = None;
+        // HTTP/1.0 connections are only kept alive when asked for.
+        let mut close = version == "HTTP/1.0";
+        let mut date = None;
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name.trim(), value.trim()),
+                _ => continue,
+            };
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.parse().ok()?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                transfer_encoding = value.rsplit(',').next().map(str::trim);
+            } else if name.eq_ignore_ascii_case("connection") {
+                for option in value.split(',').map(str::trim) {
+                    if option.eq_ignore_ascii_case("close") {
+                        close = true;
+                    } else if option.eq_ignore_ascii_case("keep-alive") {
+                        close = false;
                     }
                 }
+            } else if name.eq_ignore_ascii_case("date") {
+                date = clock::parse_http_date(value);
             }
-            Err(e) => {
-                log::warn!("Error connecting: {:?}", e);
+        }
+
+        // The transfer encoding takes precedence over the length (RFC 7230, 3.3.3).
+        let framing = match (transfer_encoding, content_length) {
+            _ if status == 204 || status == 304 || status < 200 => Framing::Length(0),
+            (Some(coding), _) if coding.eq_ignore_ascii_case("chunked") => Framing::Chunked,
+            (Some(_), _) | (None, None) => Framing::Close,
+            (None, Some(length)) => Framing::Length(length),
+        };
+        Some(Response {
+            status,
+            framing,
+            close,
+            date,
+        })
+    }
+}
+
+/// Position within a chunked body.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Chunk {
+    /// Reading the hex size of the next chunk.
+    Size(usize),
+    /// Skipping chunk extensions up to the end of the size line.
+    Extension(usize),
+    /// Reading chunk data, with this many bytes left.
+    Data(usize),
+    /// Skipping the line break after the chunk data.
+    DataEnd,
+    /// Skipping the trailer fields after the last chunk.
+    Trailer {
+        line_start: bool,
+    },
+    Done,
+}
+
+/// Collects a response body as it arrives, keeping what fits in the buffer.
+struct Body<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    framing: Framing,
+    received: usize,
+    chunk: Chunk,
+}
+
+impl<'a> Body<'a> {
+    fn new(framing: Framing, buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            framing,
+            received: 0,
+            chunk: Chunk::Size(0),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.framing {
+            Framing::Length(length) => self.received >= length,
+            Framing::Chunked => self.chunk == Chunk::Done,
+            Framing::Close => false,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) -> Result<(), HttpError> {
+        match self.framing {
+            Framing::Length(length) => {
+                let n = core::cmp::min(data.len(), length - self.received);
+                self.keep(&data[..n]);
+                self.received += n;
+            }
+            Framing::Close => self.keep(data),
+            Framing::Chunked => {
+                for &b in data {
+                    self.chunk = match (self.chunk, b) {
+                        (Chunk::Size(size), b) if (b as char).is_ascii_hexdigit() => {
+                            let digit = (b as char).to_digit(16).unwrap() as usize;
+                            let size = size
+                                .checked_mul(16)
+                                .and_then(|size| size.checked_add(digit))
+                                .ok_or(HttpError::InvalidResponse)?;
+                            Chunk::Size(size)
+                        }
+                        (Chunk::Size(size), b';') | (Chunk::Size(size), b' ') => {
+                            Chunk::Extension(size)
+                        }
+                        (Chunk::Size(size), b'\r') => Chunk::Extension(size),
+                        (Chunk::Size(0), b'\n') | (Chunk::Extension(0), b'\n') => {
+                            Chunk::Trailer { line_start: true }
+                        }
+                        (Chunk::Size(size), b'\n') | (Chunk::Extension(size), b'\n') => {
+                            Chunk::Data(size)
+                        }
+                        (Chunk::Size(_), _) => return Err(HttpError::InvalidResponse),
+                        (Chunk::Extension(size), _) => Chunk::Extension(size),
+                        (Chunk::Data(left), b) => {
+                            self.keep(&[b]);
+                            if left == 1 {
+                                Chunk::DataEnd
+                            } else {
+                                Chunk::Data(left - 1)
+                            }
+                        }
+                        (Chunk::DataEnd, b'\n') => Chunk::Size(0),
+                        (Chunk::DataEnd, _) => Chunk::DataEnd,
+                        (Chunk::Trailer { line_start }, b'\r') => Chunk::Trailer { line_start },
+                        (Chunk::Trailer { line_start: true }, b'\n') => Chunk::Done,
+                        (Chunk::Trailer { .. }, b'\n') => Chunk::Trailer { line_start: true },
+                        (Chunk::Trailer { .. }, _) => Chunk::Trailer { line_start: false },
+                        // Anything after the body belongs to no request.
+                        (Chunk::Done, _) => Chunk::Done,
+                    };
+                }
             }
         }
-        self.socket.close().await;
         Ok(())
     }
+
+    fn keep(&mut self, data: &[u8]) {
+        let n = core::cmp::min(data.len(), self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+        self.len += n;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &str) -> Response {
+        Response::parse(header.as_bytes()).unwrap()
+    }
+
+    /// Feed the data to a body one byte at a time, returning what was kept.
+    fn read_body<'a>(
+        framing: Framing,
+        buf: &'a mut [u8],
+        data: &[u8],
+    ) -> Result<(&'a [u8], bool), HttpError> {
+        let mut body = Body::new(framing, buf);
+        for b in data.chunks(1) {
+            body.feed(b)?;
+        }
+        let complete = body.is_complete();
+        let len = body.len;
+        Ok((&body.buf[..len], complete))
+    }
+
+    #[test]
+    fn content_length() {
+        let response = parse("HTTP/1.1 200 OK\r\ncontent-length: 5\r\nContent-Type: text/plain");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.framing, Framing::Length(5));
+        assert!(!response.close);
+
+        let mut buf = [0; 16];
+        let (body, complete) = read_body(response.framing, &mut buf, b"hello, world").unwrap();
+        assert_eq!(body, b"hello");
+        assert!(complete);
+    }
+
+    #[test]
+    fn body_larger_than_buffer() {
+        let mut buf = [0; 4];
+        let mut body = Body::new(Framing::Length(10), &mut buf);
+        body.feed(b"01234").unwrap();
+        assert!(!body.is_complete());
+        body.feed(b"56789").unwrap();
+        assert!(body.is_complete());
+        assert_eq!(body.len, 4);
+        assert_eq!(&buf, b"0123");
+    }
+
+    #[test]
+    fn chunked() {
+        let response =
+            parse("HTTP/1.1 200 OK\r\nContent-Length: 3\r\nTransfer-Encoding: gzip, Chunked");
+        assert_eq!(response.framing, Framing::Chunked);
+
+        let mut buf = [0; 32];
+        let data = b"4;name=value\r\nWiki\r\n5 \r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n\
+                     0;last\r\nExpires: never\r\nX-Note: done\r\n\r\n";
+        let (body, complete) = read_body(Framing::Chunked, &mut buf, data).unwrap();
+        assert_eq!(body, b"Wikipedia in\r\n\r\nchunks.");
+        assert!(complete);
+
+        // The same body in one piece, with the data not fitting the buffer.
+        let mut buf = [0; 16];
+        let mut body = Body::new(Framing::Chunked, &mut buf);
+        body.feed(data).unwrap();
+        assert!(body.is_complete());
+        assert_eq!(body.len, 16);
+    }
+
+    #[test]
+    fn chunked_incomplete() {
+        let mut buf = [0; 16];
+        let (_, complete) = read_body(Framing::Chunked, &mut buf, b"4\r\nWiki\r\n0\r\n").unwrap();
+        assert!(!complete);
+        let (_, complete) = read_body(Framing::Chunked, &mut buf, b"4\r\nWik").unwrap();
+        assert!(!complete);
+    }
+
+    #[test]
+    fn chunked_garbage() {
+        let mut buf = [0; 16];
+        assert!(read_body(Framing::Chunked, &mut buf, b"zz\r\n").is_err());
+        assert!(read_body(Framing::Chunked, &mut buf, b"\r\n").is_ok());
+        assert!(read_body(Framing::Chunked, &mut buf, b"4x\r\n").is_err());
+        assert!(read_body(Framing::Chunked, &mut buf, b"fffffffffffffffffffff\r\n").is_err());
+    }
+
+    #[test]
+    fn connection_close() {
+        let response = parse("HTTP/1.1 200 OK\r\nConnection: keep-alive, Close");
+        assert!(response.close);
+        assert_eq!(response.framing, Framing::Close);
+
+        let mut buf = [0; 16];
+        let (body, complete) = read_body(response.framing, &mut buf, b"until closed").unwrap();
+        assert_eq!(body, b"until closed");
+        assert!(!complete);
+    }
+
+    #[test]
+    fn http_1_0() {
+        assert!(parse("HTTP/1.0 200 OK\r\nContent-Length: 0").close);
+        assert!(!parse("HTTP/1.0 200 OK\r\nConnection: Keep-Alive\r\nContent-Length: 0").close);
+    }
+
+    #[test]
+    fn unknown_transfer_encoding() {
+        let response = parse("HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\nContent-Length: 5");
+        assert_eq!(response.framing, Framing::Close);
+    }
+
+    #[test]
+    fn no_body() {
+        for status in &["204 No Content", "304 Not Modified", "100 Continue"] {
+            let header = format!("HTTP/1.1 {}\r\nContent-Length: 10", status);
+            assert_eq!(parse(&header).framing, Framing::Length(0));
+        }
+    }
+
+    #[test]
+    fn garbage_header() {
+        assert!(Response::parse(b"").is_none());
+        assert!(Response::parse(b"garbage").is_none());
+        assert!(Response::parse(b"HTTP/1.1").is_none());
+        assert!(Response::parse(b"HTTP/1.1 OK 200").is_none());
+        assert!(Response::parse(b"HTTP/1.1 200 OK\r\nContent-Length: five").is_none());
+        assert!(Response::parse(b"HTTP/1.1 200 OK\r\nContent-Length: -1").is_none());
+        assert!(Response::parse(b"HTTP/1.1 200 \xff\xfe").is_none());
+
+        // Lines that are not header fields are skipped.
+        let response = parse("HTTP/1.1 500 Oops\r\nnonsense\r\n\r\nContent-Length: 2");
+        assert_eq!(response.status, 500);
+        assert_eq!(response.framing, Framing::Length(2));
+    }
 }
@@ -0,0 +1,50 @@
+use drogue_device::actors::led::matrix::{fonts::frame_5x5, Frame};
+
+/// Device status shown as an icon on the LED matrix.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    WifiConnecting,
+    WifiJoined,
+    UploadOk,
+    UploadFailed,
+    SensorFault,
+    /// The soil is dry and the plant needs watering.
+    Thirsty,
+    /// Waiting for the soil probe to be put in wet soil to finish calibration.
+    Calibrating,
+}
+
+impl Status {
+    /// Statuses of higher priority are kept on the display over those of lower priority.
+    pub fn priority(&self) -> u8 {
+        match self {
+            Status::WifiConnecting | Status::WifiJoined | Status::UploadOk => 0,
+            Status::UploadFailed => 1,
+            Status::Calibrating => 2,
+            Status::Thirsty => 3,
+            Status::SensorFault => 4,
+        }
+    }
+
+    /// Whether the status is a condition shown until it is cleared, rather than an
+    /// event shown for a while.
+    pub fn is_condition(&self) -> bool {
+        matches!(
+            self,
+            Status::Calibrating | Status::Thirsty | Status::SensorFault
+        )
+    }
+
+    pub fn icon(&self) -> Frame<5, 5> {
+        let rows = match self {
+            Status::WifiConnecting => [0b00000, 0b00000, 0b01110, 0b00000, 0b00100],
+            Status::WifiJoined => [0b01110, 0b10001, 0b01110, 0b00000, 0b00100],
+            Status::UploadOk => [0b00000, 0b00001, 0b00010, 0b10100, 0b01000],
+            Status::UploadFailed => [0b10001, 0b01010, 0b00100, 0b01010, 0b10001],
+            Status::SensorFault => [0b00100, 0b00100, 0b00100, 0b00000, 0b00100],
+            Status::Thirsty => [0b00100, 0b01110, 0b11111, 0b11111, 0b01110],
+            Status::Calibrating => [0b01010, 0b01010, 0b01010, 0b01010, 0b01010],
+        };
+        frame_5x5(&rows)
+    }
+}
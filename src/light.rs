@@ -0,0 +1,161 @@
+use embassy::time::{Duration, Timer};
+use embassy::traits::i2c::I2c;
+
+/// Photosynthetic photon flux density in µmol/m²/s per lux of sunlight.
+const PPFD_PER_LUX: f32 = 0.0185;
+
+/// Longest gap between readings integrated into the daily light integral. Light is
+/// not accounted for across longer gaps, rather than guessing what it was.
+const MAX_GAP: u32 = 2 * 3600;
+
+#[derive(Debug)]
+pub enum LightError<E> {
+    I2c(E),
+}
+
+/// Ambient light sensors on the I²C bus.
+#[derive(Clone, Copy, Debug)]
+pub enum LightSensor {
+    /// BH1750 at address 0x23, or 0x5C with the ADDR pin high.
+    Bh1750 { address: u8 },
+    /// VEML7700 at address 0x10.
+    Veml7700,
+}
+
+const VEML7700_ADDRESS: u8 = 0x10;
+
+impl LightSensor {
+    /// Read the illuminance in lux.
+    pub async fn read<I: I2c>(&self, i2c: &mut I) -> Result<f32, LightError<I::Error>> {
+        match *self {
+            LightSensor::Bh1750 { address } => {
+                // One time high resolution measurement, powering down afterwards.
+                i2c.write(address, &[0x20]).await.map_err(LightError::I2c)?;
+                Timer::after(Duration::from_millis(180)).await;
+                let mut data = [0; 2];
+                i2c.read(address, &mut data)
+                    .await
+                    .map_err(LightError::I2c)?;
+                Ok(u16::from_be_bytes(data) as f32 / 1.2)
+            }
+            LightSensor::Veml7700 => {
+                // Power on with gain 1 and 100 ms integration time, then wait for the
+                // first integration to complete.
+                i2c.write(VEML7700_ADDRESS, &[0x00, 0x00, 0x00])
+                    .await
+                    .map_err(LightError::I2c)?;
+                Timer::after(Duration::from_millis(120)).await;
+                let mut data = [0; 2];
+                i2c.write_read(VEML7700_ADDRESS, &[0x04], &mut data)
+                    .await
+                    .map_err(LightError::I2c)?;
+                // Power down until the next reading.
+                i2c.write(VEML7700_ADDRESS, &[0x00, 0x01, 0x00])
+                    .await
+                    .map_err(LightError::I2c)?;
+
+                let lux = u16::from_le_bytes(data) as f32 * 0.0576;
+                // Correct the non-linearity at high illuminance, as per the application note.
+                Ok(if lux > 1000.0 {
+                    let lux2 = lux * lux;
+                    6.0135e-13 * lux2 * lux2 - 9.3924e-9 * lux2 * lux
+                        + 8.1488e-5 * lux2
+                        + 1.0023 * lux
+                } else {
+                    lux
+                })
+            }
+        }
+    }
+}
+
+/// Accumulates the daily light integral, the photons received per day in mol/m², from
+/// readings taken through the day.
+pub struct DailyLightIntegral {
+    day: Option<u32>,
+    last: Option<(u32, f32)>,
+    total: f32,
+}
+
+impl DailyLightIntegral {
+    pub const fn new() -> Self {
+        Self {
+            day: None,
+            last: None,
+            total: 0.0,
+        }
+    }
+
+    /// Add a reading taken at `now` seconds on the given day, returning the light
+    /// integral for the day so far. Each reading is taken to last until the next one.
+    pub fn add(&mut self, now: u32, day: u32, lux: f32) -> f32 {
+        if self.day != Some(day) {
+            self.day.replace(day);
+            self.total = 0.0;
+        }
+
+        let ppfd = lux * PPFD_PER_LUX;
+        if let Some((then, last)) = self.last.replace((now, ppfd)) {
+            let elapsed = now.wrapping_sub(then);
+            if elapsed <= MAX_GAP {
+                self.total += (last + ppfd) / 2.0 * elapsed as f32 / 1_000_000.0;
+            }
+        }
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_near;
+
+    /// 10000 lx of sunlight, 185 µmol/m²/s.
+    const LUX: f32 = 10000.0;
+
+    #[test]
+    fn integrates_readings() {
+        let mut dli = DailyLightIntegral::new();
+        assert_near(dli.add(3600, 0, LUX), 0.0, 1e-4);
+        for i in 1..=6 {
+            dli.add(3600 + i * 600, 0, LUX);
+        }
+        // An hour at 185 µmol/m²/s.
+        assert_near(dli.total, 0.666, 1e-4);
+        // Light rising linearly from 10000 to 20000 lx over 10 minutes.
+        assert_near(dli.add(7800, 0, 2.0 * LUX), 0.666 + 0.1665, 1e-4);
+    }
+
+    #[test]
+    fn new_day_starts_over() {
+        let mut dli = DailyLightIntegral::new();
+        dli.add(0, 0, LUX);
+        assert_near(dli.add(3600, 0, LUX), 0.666, 1e-4);
+        // The time since the last reading counts towards the new day.
+        assert_near(dli.add(4200, 1, LUX), 0.111, 1e-4);
+        assert_near(dli.add(4800, 1, LUX), 0.222, 1e-4);
+    }
+
+    #[test]
+    fn long_gap_skipped() {
+        let mut dli = DailyLightIntegral::new();
+        dli.add(0, 0, LUX);
+        assert_near(dli.add(MAX_GAP, 0, LUX), 1.332, 1e-4);
+        assert_near(dli.add(2 * MAX_GAP + 1, 0, LUX), 1.332, 1e-4);
+        assert_near(dli.add(2 * MAX_GAP + 601, 0, LUX), 1.443, 1e-4);
+    }
+
+    #[test]
+    fn clock_set() {
+        let mut dli = DailyLightIntegral::new();
+        // Uptime is used until the clock is set.
+        dli.add(600, 0, LUX);
+        assert_near(dli.add(1200, 0, LUX), 0.111, 1e-4);
+        let now = 1_634_631_151;
+        let day = now / 86400;
+        assert_near(dli.add(now, day, LUX), 0.0, 1e-4);
+        assert_near(dli.add(now + 600, day, LUX), 0.111, 1e-4);
+        // The clock set back before the last reading.
+        assert_near(dli.add(now, day, LUX), 0.111, 1e-4);
+    }
+}
@@ -1,5 +1,5 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![macro_use]
 #![allow(incomplete_features)]
 #![feature(generic_associated_types)]
@@ -8,20 +8,67 @@
 #![feature(type_alias_impl_trait)]
 #![feature(concat_idents)]
 
+mod bme280;
+mod buttons;
+mod climate;
+mod clock;
+mod decimal;
 mod delay;
 mod dht11;
 mod display;
+mod drbg;
+mod flow;
+#[cfg(feature = "gateway")]
+mod gateway;
+mod gesture;
+mod http;
+mod icon;
+mod light;
+mod matrix;
+mod metrics;
+mod motion;
 mod network;
 mod plant_monitor;
 mod rng;
+mod rules;
+mod schedule;
+mod seesaw;
+mod sht;
+mod soil;
+mod sound;
 mod splitter;
+mod stats;
+mod temp;
+#[cfg(test)]
+mod testing;
+mod text;
+mod trust;
+mod watering;
+use buttons::*;
+use climate::ClimateSensor;
 use delay::*;
 use display::*;
+use drbg::*;
+use flow::*;
+#[cfg(feature = "gateway")]
+use gateway::*;
+use http::Credentials;
+use icon::Status;
+use light::*;
+use matrix::Brightness;
+use motion::*;
 use network::*;
 use plant_monitor::*;
 use rng::*;
+use rules::*;
+use schedule::*;
+use soil::SoilSensor;
+use sound::*;
 use splitter::*;
+use trust::*;
+use watering::*;
 
+#[cfg(not(test))]
 use panic_reset as _;
 //use log::LevelFilter;
 //use panic_probe as _;
@@ -29,22 +76,23 @@ use panic_reset as _;
 //use rtt_target::rtt_init_print;
 
 use drogue_device::{
-    actors::{button::Button, socket::*, ticker::Ticker, wifi::esp8266::*},
+    actors::{socket::*, ticker::Ticker, wifi::esp8266::*},
     drivers::wifi::esp8266::*,
     traits::{ip::*, tcp::*, wifi::*},
     *,
 };
 use drogue_tls::*;
 
-use embassy::time::Duration;
+use embassy::{time::Duration, util::Forever};
 
 use embassy_nrf::{
     buffered_uarte::BufferedUarte,
     gpio::{AnyPin, FlexPin, Input, Level, NoPin, Output, OutputDrive, Pin, Pull},
     gpiote::PortInput,
-    interrupt,
-    peripherals::{P0_09, P0_10, P0_14, TIMER0, UARTE0},
+    interrupt::{self, InterruptExt},
+    peripherals::{P0_09, P0_10, TIMER0, UARTE0},
     saadc::*,
+    twim::{self, Twim},
     uarte, Peripherals,
 };
 use nrf52833_pac as pac;
@@ -59,16 +107,177 @@ const PORT: u16 = 5000;
 const USERNAME: &str = include_str!(concat!(env!("OUT_DIR"), "/config/username.txt"));
 const PASSWORD: &str = include_str!(concat!(env!("OUT_DIR"), "/config/password.txt"));
 
+// DER encoded client certificate and private key, used instead of the username and password when present.
+const CLIENT_CERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/config/client-cert.der"));
+const CLIENT_KEY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/config/client-key.der"));
+
+// LED matrix brightness, following the ambient light to dim the display at night
+const BRIGHTNESS: Brightness = Brightness::Auto;
+
+// Time between each step when scrolling text across the LED matrix
+const SCROLL_SPEED: Duration = Duration::from_millis(150);
+
+// How long status icons stay on the LED matrix
+const STATUS_DURATION: Duration = Duration::from_secs(30);
+
+// Time between measurements, until changed by the server
+const INTERVAL: Duration = Duration::from_secs(600);
+
+// Measure more often while readings change fast or are close to the thirsty threshold,
+// and less often while they are stable, within these bounds
+const ADAPTIVE_SAMPLING: bool = true;
+const MIN_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Soil moisture in percent below which the plant asks to be watered
+const THIRSTY_BELOW: u8 = 20;
+
+// Water the plant in pulses when the soil moisture in percent drops below WATER_BELOW,
+// until it reaches WATER_UNTIL, giving the water time to soak in after each pulse
+const WATER_BELOW: u8 = 25;
+const WATER_UNTIL: u8 = 45;
+const PUMP_PULSE: Duration = Duration::from_secs(5);
+const SOAK: Duration = Duration::from_secs(120);
+
+// Whether a flow meter on pin P0_12 measures the water delivered. Each pulse then
+// runs until PULSE_VOLUME ml have been delivered instead of for PUMP_PULSE.
+const FLOW_METER: bool = false;
+const PULSE_VOLUME: u32 = 50;
+
+// Pulses per litre of the flow meter, around 450 for the common YF-S201
+const FLOW_PULSES_PER_LITRE: u32 = 450;
+
+// Safety limits on watering, so a probe out of the soil can't flood the room
+const INTERLOCKS: Interlocks = Interlocks {
+    max_pulse: Duration::from_secs(15),
+    max_pulses_per_day: 12,
+    check_after: 3,
+    min_rise: 3,
+};
+
+// Whether a float switch on the reservoir pin tells when the water tank is empty
+const RESERVOIR: bool = false;
+
+// Alerts raised when readings stay beyond the limits for the plant. Critical alerts
+// sound the alarm.
+const RULES: &[Rule] = &[
+    Rule {
+        name: "SOIL DRY",
+        metric: Metric::Moisture,
+        limit: Limit::Below(THIRSTY_BELOW as f32),
+        hysteresis: 5.0,
+        debounce: 30 * 60,
+        severity: Severity::Critical,
+    },
+    Rule {
+        name: "TOO COLD",
+        metric: Metric::Temperature,
+        limit: Limit::Below(10.0),
+        hysteresis: 1.0,
+        debounce: 10 * 60,
+        severity: Severity::Warning,
+    },
+    Rule {
+        name: "TOO HOT",
+        metric: Metric::Temperature,
+        limit: Limit::Above(32.0),
+        hysteresis: 1.0,
+        debounce: 10 * 60,
+        severity: Severity::Warning,
+    },
+    Rule {
+        name: "AIR DRY",
+        metric: Metric::Humidity,
+        limit: Limit::Below(30.0),
+        hysteresis: 5.0,
+        debounce: 60 * 60,
+        severity: Severity::Info,
+    },
+    Rule {
+        name: "VPD HIGH",
+        metric: Metric::Vpd,
+        limit: Limit::Above(1.6),
+        hysteresis: 0.2,
+        debounce: 60 * 60,
+        severity: Severity::Warning,
+    },
+];
+
+// Chirps played on the speaker while a critical alert is active
+const ALARM: &[Tone] = &[
+    Tone::new(2000, 80),
+    Tone::new(0, 60),
+    Tone::new(2500, 80),
+    Tone::new(0, 60),
+    Tone::new(3000, 120),
+];
+
+// Offset of local time from UTC in seconds
+const UTC_OFFSET: i32 = 3600;
+
+// The alarm stays silent at night, local time
+const QUIET_HOURS: Option<QuietHours> = Some(QuietHours {
+    from: 22,
+    to: 7,
+    utc_offset: UTC_OFFSET,
+});
+
+// Temperature and humidity sensor, the DHT11 on the temperature pin or one of the more
+// accurate sensors on the external I2C bus, such as ClimateSensor::Sht3x { address: 0x44 }
+// or ClimateSensor::Bme280(bme280::Bme280::new(0x76))
+const CLIMATE_SENSOR: ClimateSensor = ClimateSensor::Dht11;
+
+// Soil moisture sensor, the analog probe or seesaw capacitive sensors on the external
+// I2C bus, such as SoilSensor::Seesaw(&[seesaw::Seesaw { address: 0x36 }])
+const SOIL_SENSOR: SoilSensor = SoilSensor::Analog;
+
+// Soil samples with the probe in dry and in wet soil, such as
+// SoilCalibration { dry: 11400, wet: 5500 }, by default typical samples of the sensor
+const SOIL_CALIBRATION: SoilCalibration = SOIL_SENSOR.calibration();
+
+// Offset added to the board temperature to report the air temperature when the climate
+// sensor fails, or None to report no temperature then
+const TEMPERATURE_FALLBACK: Option<f32> = Some(-3.0);
+
+// Leaf temperature relative to the air for the vapour pressure deficit, typically a
+// couple of degrees cooler for plants under natural light
+const LEAF_OFFSET: f32 = -2.0;
+
+// Light sensor on the external I2C bus, if any, such as
+// Some(LightSensor::Bh1750 { address: 0x23 })
+const LIGHT_SENSOR: Option<LightSensor> = None;
+
+// Movement of the pot in mg, and tilt from upright in degrees, raising alerts as the
+// pot gets moved or knocked over
+const MOTION_LIMITS: MotionLimits = MotionLimits {
+    threshold: 250,
+    max_tilt: 45,
+};
+
+// How long holding button B keeps the alarm silent
+const SNOOZE: Duration = Duration::from_secs(8 * 3600);
+
+// How long a button must be held for a long press
+const LONG_PRESS: Duration = Duration::from_secs(2);
+
+// Reseed the random generator used for TLS from the hardware RNG this often
+const RESEED_BYTES: usize = 65536;
+const RESEED_INTERVAL: Duration = Duration::from_secs(3600);
+
 // static LOGGER: RTTLogger = RTTLogger::new(LevelFilter::Info);
 
 type UART = BufferedUarte<'static, UARTE0, TIMER0>;
 type ENABLE = Output<'static, P0_09>;
 type RESET = Output<'static, P0_10>;
+#[cfg(not(feature = "gateway"))]
+type AppRng = Drbg<&'static Seeds>;
+#[cfg(feature = "gateway")]
+type AppRng = SoftdeviceRng;
 type AppSocket =
-    TlsSocket<'static, Socket<'static, Esp8266Controller<'static>>, Rng, Aes128GcmSha256>;
+    TlsSocket<'static, Socket<'static, Esp8266Controller<'static>>, AppRng, Aes128GcmSha256>;
 
 type Network = NetworkEndpoint<AppSocket, Measurement>;
-type Sink = Splitter<'static, Measurement, Network, <Display as Package>::Primary>;
+type Sink = Splitter<'static, Event, Network, <Display as Package>::Primary>;
 type Monitor = PlantMonitor<'static, Sink, Delay>;
 
 pub struct MyDevice {
@@ -77,24 +286,44 @@ pub struct MyDevice {
     network: ActorContext<'static, Network>,
     sink: ActorContext<'static, Sink>,
     monitor: ActorContext<'static, Monitor>,
+    speaker: ActorContext<'static, Speaker>,
+    watering: ActorContext<'static, Watering<'static, Sink>>,
     ticker: ActorContext<'static, Ticker<'static, Monitor>>,
-    button: ActorContext<'static, Button<'static, PortInput<'static, P0_14>, Monitor>>,
+    buttons: ActorContext<'static, Buttons<Monitor>>,
+    motion: ActorContext<'static, Motion<Monitor>>,
+    #[cfg(feature = "gateway")]
+    gateway: ActorContext<'static, Gateway<Network>>,
 }
 
 static mut TLS_BUFFER: [u8; 16384] = [0; 16384];
+static PINNED_KEY: Forever<PinnedKey> = Forever::new();
+#[cfg(not(feature = "gateway"))]
+static SEEDS: Seeds = Seeds::new();
 static DEVICE: DeviceContext<MyDevice> = DeviceContext::new();
 
 fn output_pin(pin: AnyPin) -> Output<'static, AnyPin> {
     Output::new(pin, Level::Low, OutputDrive::Standard)
 }
 
-#[embassy::main]
+// The SoftDevice reserves interrupt priorities 0, 1 and 4 for itself.
+fn config() -> embassy_nrf::config::Config {
+    let mut config = embassy_nrf::config::Config::default();
+    if cfg!(feature = "gateway") {
+        config.gpiote_interrupt_priority = interrupt::Priority::P2;
+        config.time_interrupt_priority = interrupt::Priority::P2;
+    }
+    config
+}
+
+#[cfg(not(test))]
+#[embassy::main(config = "config()")]
 async fn main(spawner: embassy::executor::Spawner, p: Peripherals) {
     //rtt_init_print!();
     //log::set_logger(&LOGGER).unwrap();
     //log::set_max_level(log::LevelFilter::Info);
 
-    let button_port = PortInput::new(Input::new(p.P0_14, Pull::Up));
+    let button_a = PortInput::new(Input::new(p.P0_14, Pull::Up));
+    let button_b = PortInput::new(Input::new(p.P0_23, Pull::Up));
 
     let mut config = uarte::Config::default();
     config.parity = uarte::Parity::EXCLUDED;
@@ -104,6 +333,8 @@ async fn main(spawner: embassy::executor::Spawner, p: Peripherals) {
     static mut RX_BUFFER: [u8; 8192] = [0u8; 8192];
 
     let irq = interrupt::take!(UARTE0_UART0);
+    #[cfg(feature = "gateway")]
+    irq.set_priority(interrupt::Priority::P2);
     let u = unsafe {
         BufferedUarte::new(
             p.UARTE0,
@@ -131,11 +362,11 @@ async fn main(spawner: embassy::executor::Spawner, p: Peripherals) {
     ];
 
     let cols = [
-        output_pin(p.P0_28.degrade()),
-        output_pin(p.P0_11.degrade()),
-        output_pin(p.P0_31.degrade()),
-        output_pin(p.P1_05.degrade()),
-        output_pin(p.P0_30.degrade()),
+        FlexPin::new(p.P0_28.degrade()),
+        FlexPin::new(p.P0_11.degrade()),
+        FlexPin::new(p.P0_31.degrade()),
+        FlexPin::new(p.P1_05.degrade()),
+        FlexPin::new(p.P0_30.degrade()),
     ];
 
     let enable_pin = Output::new(p.P0_09, Level::Low, OutputDrive::Standard);
@@ -143,37 +374,160 @@ async fn main(spawner: embassy::executor::Spawner, p: Peripherals) {
 
     let temp_pin = FlexPin::new(p.P0_02);
     let soil_pin = p.P0_04;
-    let adc = OneShot::new(p.SAADC, interrupt::take!(SAADC), Default::default());
+    let adc_irq = interrupt::take!(SAADC);
+    #[cfg(feature = "gateway")]
+    adc_irq.set_priority(interrupt::Priority::P2);
+    let adc = OneShot::new(p.SAADC, adc_irq, Default::default());
+
+    // External I2C bus on the edge connector
+    let i2c_irq = interrupt::take!(SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0);
+    #[cfg(feature = "gateway")]
+    i2c_irq.set_priority(interrupt::Priority::P2);
+    let i2c = Twim::new(
+        p.TWISPI0,
+        i2c_irq,
+        p.P1_00,
+        p.P0_26,
+        twim::Config::default(),
+    );
+
+    // Internal I2C bus to the accelerometer, which signals movement on P0_25
+    let motion_irq = interrupt::take!(SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1);
+    #[cfg(feature = "gateway")]
+    motion_irq.set_priority(interrupt::Priority::P2);
+    let motion_i2c = Twim::new(
+        p.TWISPI1,
+        motion_irq,
+        p.P0_16,
+        p.P0_08,
+        twim::Config::default(),
+    );
+    let motion_int = PortInput::new(Input::new(p.P0_25, Pull::Up));
 
     let cp = unsafe { cortex_m::Peripherals::steal() };
+    let pp = pac::Peripherals::take().unwrap();
+
+    #[cfg(feature = "gateway")]
+    let sd = enable_softdevice();
+
+    // The SoftDevice keeps the temperature sensor to itself.
+    #[cfg(not(feature = "gateway"))]
+    let board_temp = Some(temp::Temp::new(pp.TEMP, interrupt::take!(TEMP)));
+    #[cfg(feature = "gateway")]
+    let board_temp = Some(temp::Temp::softdevice(sd));
+    #[cfg(not(feature = "gateway"))]
+    let rng = {
+        let rng = Rng::new(pp.RNG, interrupt::take!(RNG));
+        spawner.spawn(seed_task(rng, &SEEDS)).unwrap();
+        // A TLS handshake started before the first seed would fail.
+        SEEDS.ready().await;
+        Drbg::new(&SEEDS, Uptime, RESEED_BYTES, RESEED_INTERVAL)
+    };
+    #[cfg(feature = "gateway")]
+    let rng = SoftdeviceRng::new(sd);
+
+    let pinned_key: Option<&'static PinnedKey> = match PinnedKey::configured() {
+        Some(key) => Some(PINNED_KEY.put(key)),
+        None if cfg!(feature = "insecure") => {
+            log::warn!("No server key pinned, server certificate will not be verified");
+            None
+        }
+        None => panic!("No server key pinned in config/server-key.sha256"),
+    };
 
     DEVICE.configure(MyDevice {
-        ticker: ActorContext::new(Ticker::new(
-            Duration::from_secs(600),
-            Command::TakeMeasurement,
-        )),
-        button: ActorContext::new(Button::new(button_port)),
+        ticker: ActorContext::new(Ticker::new(Duration::from_secs(1), Command::Tick)),
+        buttons: ActorContext::new(Buttons::new(button_a, button_b, LONG_PRESS)),
+        motion: ActorContext::new(Motion::new(motion_i2c, motion_int, MOTION_LIMITS)),
         wifi: Esp8266Wifi::new(u, enable_pin, reset_pin),
         network: ActorContext::new(NetworkEndpoint::new(
             IP,
             PORT,
-            USERNAME.trim_end(),
-            PASSWORD.trim_end(),
+            HOST,
+            if CLIENT_CERT.is_empty() {
+                Credentials::Basic {
+                    username: USERNAME.trim_end(),
+                    password: PASSWORD.trim_end(),
+                }
+            } else {
+                Credentials::ClientCertificate
+            },
+            pinned_key,
         )),
         sink: ActorContext::new(Splitter::new()),
         monitor: ActorContext::new(PlantMonitor::new(
+            CLIMATE_SENSOR,
             temp_pin,
             soil_pin,
+            SOIL_SENSOR,
+            SOIL_CALIBRATION,
+            THIRSTY_BELOW,
+            Rules::new(RULES),
+            Schedule::new(
+                INTERVAL.as_secs() as u32,
+                MIN_INTERVAL.as_secs() as u32,
+                MAX_INTERVAL.as_secs() as u32,
+                ADAPTIVE_SAMPLING,
+            ),
             adc,
+            i2c,
+            LIGHT_SENSOR,
+            board_temp,
+            TEMPERATURE_FALLBACK,
+            LEAF_OFFSET,
+            UTC_OFFSET,
             Delay::new(cp.SYST),
         )),
-        display: Display::new(rows, cols),
+        watering: ActorContext::new(Watering::new(
+            Output::new(p.P0_03, Level::Low, OutputDrive::Standard),
+            if RESERVOIR {
+                Some(Input::new(p.P1_02, Pull::Up))
+            } else {
+                None
+            },
+            if FLOW_METER {
+                Some(FlowMeter::new(
+                    pp.TIMER1,
+                    &pp.GPIOTE,
+                    &pp.PPI,
+                    Input::new(p.P0_12, Pull::Up),
+                    p.GPIOTE_CH7,
+                    p.PPI_CH2,
+                    FLOW_PULSES_PER_LITRE,
+                ))
+            } else {
+                None
+            },
+            WATER_BELOW,
+            WATER_UNTIL,
+            PUMP_PULSE,
+            PULSE_VOLUME,
+            SOAK,
+            INTERLOCKS,
+        )),
+        speaker: ActorContext::new(Speaker::new(pp.PWM0, p.P0_00, ALARM, QUIET_HOURS, SNOOZE)),
+        display: Display::new(
+            rows,
+            cols,
+            BRIGHTNESS,
+            SCROLL_SPEED,
+            DisplayMode::Temperature,
+            STATUS_DURATION,
+        ),
+        #[cfg(feature = "gateway")]
+        gateway: ActorContext::new(Gateway::new()),
     });
 
+    #[cfg(feature = "gateway")]
+    spawner.spawn(softdevice_task(sd)).unwrap();
+
     DEVICE
         .mount(|device| async move {
             let display = device.display.mount((), spawner);
             let mut wifi = device.wifi.mount((), spawner);
+            display
+                .notify(DisplayCommand::Status(Status::WifiConnecting))
+                .unwrap();
             wifi.join(Join::Wpa {
                 ssid: WIFI_SSID.trim_end(),
                 password: WIFI_PSK.trim_end(),
@@ -181,20 +535,36 @@ async fn main(spawner: embassy::executor::Spawner, p: Peripherals) {
             .await
             .expect("Error joining wifi");
             log::info!("Joined access point");
+            display
+                .notify(DisplayCommand::Status(Status::WifiJoined))
+                .unwrap();
 
             let socket = Socket::new(wifi, wifi.open().await);
-            let socket = TlsSocket::wrap(
-                socket,
-                TlsContext::new(Rng::new(pac::Peripherals::take().unwrap().RNG), unsafe {
-                    &mut TLS_BUFFER
-                })
-                .with_server_name(HOST.trim_end()),
-            );
-            let network = device.network.mount(socket, spawner);
+            let mut context =
+                TlsContext::new(rng, unsafe { &mut TLS_BUFFER }).with_server_name(HOST.trim_end());
+            if let Some(key) = pinned_key {
+                context = context.with_verifier(key);
+            }
+            if !CLIENT_CERT.is_empty() {
+                context = context.with_client_cert(Certificate::X509(CLIENT_CERT), CLIENT_KEY);
+            }
+            let socket = TlsSocket::wrap(socket, context);
+            let network = device.network.mount((socket, display), spawner);
             let sink = device.sink.mount((network, display), spawner);
-            let monitor = device.monitor.mount(sink, spawner);
+            let speaker = device.speaker.mount((), spawner);
+            let watering = device.watering.mount(sink, spawner);
+            let monitor = device
+                .monitor
+                .mount((sink, display, speaker, watering), spawner);
             device.ticker.mount(monitor, spawner);
-            device.button.mount(monitor, spawner);
+            device.buttons.mount((monitor, display), spawner);
+            device.motion.mount(monitor, spawner);
+
+            #[cfg(feature = "gateway")]
+            {
+                let gateway = device.gateway.mount(network, spawner);
+                spawner.spawn(scan_task(sd, gateway)).unwrap();
+            }
         })
         .await;
 }
@@ -0,0 +1,187 @@
+use core::future::Future;
+use core::pin::Pin;
+use drogue_device::{
+    actors::led::matrix::{fonts::frame_5x5, Frame, ToFrame},
+    *,
+};
+use embassy::time::{Duration, Instant, Timer};
+use embassy_nrf::gpio::{AnyPin, FlexPin, Output, OutputDrive, Pull};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// Time each row is scanned for, giving a refresh rate of 100 Hz for the whole matrix.
+pub const ROW_PERIOD: Duration = Duration::from_micros(2000);
+
+pub const MAX_BRIGHTNESS: u8 = 10;
+
+/// Time between ambient light readings in automatic mode.
+pub const SENSE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Discharge times of the reverse biased LED in bright light and in the dark. Beyond the
+/// dark limit the room is considered dark and the display is dimmed to the minimum.
+const SENSE_BRIGHT: Duration = Duration::from_micros(500);
+const SENSE_DARK: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Brightness {
+    /// Fixed level between 1 and `MAX_BRIGHTNESS`.
+    Level(u8),
+    /// Follow the ambient light, dimming the display at night.
+    Auto,
+}
+
+#[derive(Clone, Copy)]
+pub enum MatrixCommand<'m> {
+    ApplyFrame(&'m dyn ToFrame<5, 5>),
+    Clear,
+    /// Scan the next row.
+    Render,
+    /// Read the ambient light and adjust the brightness to it, in automatic mode.
+    SenseLight,
+}
+
+/// LED matrix driver scanning one row at a time, with brightness set by how long each
+/// row is lit during its scan period.
+///
+/// The LEDs double as a light sensor: reverse biased they act as small capacitors that
+/// discharge faster the more light falls on them. Light is sensed on its own schedule
+/// between row scans, whether or not the display is refreshed, blanking the display for
+/// up to `SENSE_DARK` each time.
+pub struct Matrix {
+    rows: [Output<'static, AnyPin>; 5],
+    cols: [FlexPin<'static, AnyPin>; 5],
+    frame: Frame<5, 5>,
+    row: usize,
+    brightness: Brightness,
+    level: u8,
+}
+
+impl Matrix {
+    pub fn new(
+        rows: [Output<'static, AnyPin>; 5],
+        mut cols: [FlexPin<'static, AnyPin>; 5],
+        brightness: Brightness,
+    ) -> Self {
+        for col in cols.iter_mut() {
+            col.set_high().ok();
+            col.set_as_output(OutputDrive::Standard);
+        }
+        Self {
+            rows,
+            cols,
+            frame: frame_5x5(&[0; 5]),
+            row: 0,
+            brightness,
+            level: match brightness {
+                Brightness::Level(level) => {
+                    core::cmp::max(1, core::cmp::min(level, MAX_BRIGHTNESS))
+                }
+                Brightness::Auto => MAX_BRIGHTNESS,
+            },
+        }
+    }
+
+    fn blank(&mut self) {
+        for row in self.rows.iter_mut() {
+            row.set_low().ok();
+        }
+        for col in self.cols.iter_mut() {
+            col.set_high().ok();
+        }
+    }
+
+    async fn render(&mut self) {
+        self.blank();
+
+        self.row = (self.row + 1) % 5;
+        for (x, col) in self.cols.iter_mut().enumerate() {
+            if self.frame.is_set(x, self.row) {
+                col.set_low().ok();
+            }
+        }
+        self.rows[self.row].set_high().ok();
+
+        // Leave the row lit until the next scan at full brightness.
+        if self.level < MAX_BRIGHTNESS {
+            let on = ROW_PERIOD.as_micros() * self.level as u64 / MAX_BRIGHTNESS as u64;
+            Timer::after(Duration::from_micros(on)).await;
+            self.blank();
+        }
+    }
+
+    /// Adjust the brightness to the ambient light in automatic mode.
+    async fn adjust(&mut self) {
+        if self.brightness != Brightness::Auto {
+            return;
+        }
+        self.blank();
+        let elapsed = self.sense_light().await;
+        self.level = level_for(elapsed);
+        log::trace!(
+            "Ambient light sensed in {:?}, brightness {}",
+            elapsed,
+            self.level
+        );
+    }
+
+    /// Charge the middle column of LEDs in reverse and measure how long they take to
+    /// discharge.
+    async fn sense_light(&mut self) -> Duration {
+        let col = &mut self.cols[2];
+        col.set_high().ok();
+        Timer::after(Duration::from_micros(100)).await;
+
+        col.set_as_input(Pull::None);
+        let start = Instant::now();
+        let mut elapsed = Duration::from_ticks(0);
+        while col.is_high().unwrap_or(false) && elapsed < SENSE_DARK {
+            Timer::after(Duration::from_micros(100)).await;
+            elapsed = Instant::now() - start;
+        }
+
+        col.set_high().ok();
+        col.set_as_output(OutputDrive::Standard);
+        elapsed
+    }
+}
+
+/// Scale brightness with the discharge time between bright light and darkness.
+fn level_for(elapsed: Duration) -> u8 {
+    if elapsed <= SENSE_BRIGHT {
+        MAX_BRIGHTNESS
+    } else if elapsed >= SENSE_DARK {
+        1
+    } else {
+        let range = (SENSE_DARK - SENSE_BRIGHT).as_micros();
+        let darkness = (elapsed - SENSE_BRIGHT).as_micros();
+        (MAX_BRIGHTNESS as u64 - (MAX_BRIGHTNESS as u64 - 1) * darkness / range) as u8
+    }
+}
+
+impl Actor for Matrix {
+    type Configuration = ();
+
+    type Message<'m> = MatrixCommand<'m>;
+    type OnStartFuture<'m> = impl Future<Output = ()> + 'm;
+    type OnMessageFuture<'m> = impl Future<Output = ()> + 'm;
+
+    fn on_mount(&mut self, _: Address<'static, Self>, _: Self::Configuration) {}
+
+    fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
+        async move {}
+    }
+
+    fn on_message<'m>(
+        self: Pin<&'m mut Self>,
+        message: Self::Message<'m>,
+    ) -> Self::OnMessageFuture<'m> {
+        async move {
+            let this = unsafe { self.get_unchecked_mut() };
+            match message {
+                MatrixCommand::ApplyFrame(frame) => this.frame = frame.to_frame(),
+                MatrixCommand::Clear => this.frame = frame_5x5(&[0; 5]),
+                MatrixCommand::Render => this.render().await,
+                MatrixCommand::SenseLight => this.adjust().await,
+            }
+        }
+    }
+}
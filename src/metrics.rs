@@ -0,0 +1,178 @@
+//! Metrics derived from the temperature and relative humidity.
+//!
+//! Vapour pressures follow the Magnus formula with the coefficients of Sonntag (1990),
+//! within 0.3 % of the reference tables between -45 and 60 °C:
+//!
+//! | °C | Table hPa | Magnus hPa |
+//! |----|-----------|------------|
+//! | 0  | 6.112     | 6.112      |
+//! | 10 | 12.28     | 12.26      |
+//! | 20 | 23.39     | 23.33      |
+//! | 30 | 42.46     | 42.34      |
+//! | 40 | 73.81     | 73.67      |
+
+const A: f32 = 17.62;
+const B: f32 = 243.12;
+/// Saturation vapour pressure at 0 °C in hPa.
+const E0: f32 = 6.112;
+
+/// Specific gas constant of water vapour in J/(kg K).
+const RV: f32 = 461.5;
+
+const LN_2: f32 = core::f32::consts::LN_2;
+
+/// Natural logarithm of a positive number.
+///
+/// Splits off the binary exponent, leaving a mantissa in [√½, √2) for a short series.
+fn ln(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let mut exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    let mut m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+    if m > core::f32::consts::SQRT_2 {
+        m /= 2.0;
+        exponent += 1;
+    }
+    // ln(m) = 2 atanh(s), with |s| < 0.18.
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let series = s * (2.0 + s2 * (2.0 / 3.0 + s2 * (2.0 / 5.0 + s2 * (2.0 / 7.0))));
+    exponent as f32 * LN_2 + series
+}
+
+/// Exponential of a number between -87 and 88.
+///
+/// Splits off a power of two, leaving a remainder within ±ln(2)/2 for a short series.
+fn exp(x: f32) -> f32 {
+    let k = (x / LN_2 + if x < 0.0 { -0.5 } else { 0.5 }) as i32;
+    let r = x - k as f32 * LN_2;
+    let series = 1.0
+        + r * (1.0
+            + r * (1.0 / 2.0
+                + r * (1.0 / 6.0 + r * (1.0 / 24.0 + r * (1.0 / 120.0 + r * (1.0 / 720.0))))));
+    series * f32::from_bits(((k + 127) as u32) << 23)
+}
+
+/// Saturation vapour pressure over water in hPa at a temperature in °C.
+pub fn saturation_vapour_pressure(temperature: f32) -> f32 {
+    E0 * exp(A * temperature / (B + temperature))
+}
+
+/// Actual vapour pressure in hPa at a temperature in °C and relative humidity in percent.
+pub fn vapour_pressure(temperature: f32, humidity: f32) -> f32 {
+    saturation_vapour_pressure(temperature) * humidity / 100.0
+}
+
+/// Temperature in °C at which the air would be saturated, for humidity above 0 %.
+///
+/// 20 °C at 50 % gives 9.3 °C.
+pub fn dew_point(temperature: f32, humidity: f32) -> Option<f32> {
+    if humidity <= 0.0 {
+        return None;
+    }
+    let gamma = ln(humidity / 100.0) + A * temperature / (B + temperature);
+    Some(B * gamma / (A - gamma))
+}
+
+/// Mass of water vapour in the air in g/m³.
+///
+/// 20 °C at 50 % gives 8.6 g/m³, 30 °C at 80 % gives 24.2 g/m³.
+pub fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    // hPa to Pa, and kg to g.
+    vapour_pressure(temperature, humidity) * 100.0 * 1000.0 / (RV * (temperature + 273.15))
+}
+
+/// Vapour pressure deficit in kPa between the leaves and the air, for leaves
+/// `leaf_offset` °C warmer than the air, or cooler with a negative offset.
+///
+/// 25 °C at 60 % gives 1.26 kPa, 0.91 kPa with leaves 2 °C cooler.
+pub fn vpd(temperature: f32, humidity: f32, leaf_offset: f32) -> f32 {
+    let deficit = saturation_vapour_pressure(temperature + leaf_offset)
+        - vapour_pressure(temperature, humidity);
+    // Leaves below the dew point gather condensation, there is no deficit to speak of.
+    if deficit < 0.0 {
+        0.0
+    } else {
+        deficit / 10.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_near;
+
+    #[test]
+    fn ln_within_1e_6_relative() {
+        for (x, expected) in [
+            (0.01, -4.605_170_2),
+            (0.5, -0.693_147_2),
+            (1.0, 0.0),
+            (1.5, 0.405_465_1),
+            (core::f32::consts::E, 1.0),
+            (100.0, 4.605_170_2),
+        ]
+        .iter()
+        {
+            assert_near(ln(*x), *expected, 1e-6 + expected.abs() * 1e-6);
+        }
+    }
+
+    #[test]
+    fn exp_within_1e_6_relative() {
+        for (x, expected) in [
+            (-10.0, 4.539_993e-5),
+            (-1.0, 0.367_879_44),
+            (0.0, 1.0),
+            (0.5, 1.648_721_3),
+            (1.0, core::f32::consts::E),
+            (10.0, 22_026.465),
+        ]
+        .iter()
+        {
+            assert_near(exp(*x), *expected, expected * 1e-6);
+        }
+    }
+
+    #[test]
+    fn saturation_vapour_pressure_within_0_3_percent_of_tables() {
+        for (temperature, table) in [
+            (0.0, 6.112),
+            (10.0, 12.28),
+            (20.0, 23.39),
+            (30.0, 42.46),
+            (40.0, 73.81),
+        ]
+        .iter()
+        {
+            assert_near(
+                saturation_vapour_pressure(*temperature),
+                *table,
+                table * 0.003,
+            );
+        }
+    }
+
+    #[test]
+    fn dew_point_within_0_1_degrees() {
+        assert_near(dew_point(20.0, 50.0).unwrap(), 9.3, 0.1);
+        assert_near(dew_point(25.0, 100.0).unwrap(), 25.0, 0.01);
+        assert_near(dew_point(30.0, 80.0).unwrap(), 26.2, 0.1);
+        assert_eq!(dew_point(20.0, 0.0), None);
+    }
+
+    #[test]
+    fn absolute_humidity_within_0_1_grams() {
+        assert_near(absolute_humidity(20.0, 50.0), 8.6, 0.1);
+        assert_near(absolute_humidity(30.0, 80.0), 24.2, 0.1);
+        assert_eq!(absolute_humidity(20.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn vpd_within_0_01_kpa() {
+        assert_near(vpd(25.0, 60.0, 0.0), 1.26, 0.01);
+        assert_near(vpd(25.0, 60.0, -2.0), 0.91, 0.01);
+        assert_near(vpd(25.0, 100.0, 0.0), 0.0, 0.001);
+        // Leaves below the dew point.
+        assert_eq!(vpd(25.0, 90.0, -5.0), 0.0);
+    }
+}
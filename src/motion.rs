@@ -0,0 +1,333 @@
+use super::plant_monitor::Command;
+use super::rules::{Alert, Severity};
+use core::future::Future;
+use core::pin::Pin;
+use drogue_device::*;
+use embassy::time::{Duration, Timer};
+use embassy::traits::{gpio::WaitForLow, i2c::I2c};
+use embassy_nrf::{
+    gpiote::PortInput,
+    peripherals::{P0_25, TWISPI1},
+    twim::{self, Twim},
+};
+use futures::{
+    future::{select, Either},
+    pin_mut,
+};
+
+const ADDRESS: u8 = 0x19;
+const WHO_AM_I: u8 = 0x33;
+
+const REG_WHO_AM_I: u8 = 0x0F;
+const REG_CTRL_REG1: u8 = 0x20;
+const REG_CTRL_REG2: u8 = 0x21;
+const REG_CTRL_REG3: u8 = 0x22;
+const REG_CTRL_REG4: u8 = 0x23;
+const REG_CTRL_REG5: u8 = 0x24;
+const REG_CTRL_REG6: u8 = 0x25;
+const REG_REFERENCE: u8 = 0x26;
+const REG_INT1_CFG: u8 = 0x30;
+const REG_INT1_SRC: u8 = 0x31;
+const REG_INT1_THS: u8 = 0x32;
+const REG_OUT_X_L: u8 = 0x28;
+/// Set in the register address to read several registers in one go.
+const AUTO_INCREMENT: u8 = 0x80;
+
+/// How long the pot must keep still before its orientation is checked.
+const SETTLE: Duration = Duration::from_secs(5);
+
+/// Limits on movements of the pot.
+#[derive(Clone, Copy)]
+pub struct MotionLimits {
+    /// Change in acceleration in mg raising the MOVED alert, rounded down to 16 mg.
+    pub threshold: u16,
+    /// Tilt in degrees from the orientation at boot beyond which the pot is considered
+    /// knocked over, up to 90.
+    pub max_tilt: u8,
+}
+
+/// Cosine of an angle in degrees, within 0.002 of the actual value over 0-180 degrees.
+fn cos(degrees: f32) -> f32 {
+    if degrees > 90.0 {
+        return -cos(180.0 - degrees);
+    }
+    let d2 = degrees * degrees;
+    (32400.0 - 4.0 * d2) / (32400.0 + d2)
+}
+
+fn abs(x: f32) -> f32 {
+    if x < 0.0 {
+        -x
+    } else {
+        x
+    }
+}
+
+fn dot(a: &[i16; 3], b: &[i16; 3]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| *a as f32 * *b as f32)
+        .sum()
+}
+
+/// Angle in degrees between two acceleration vectors.
+///
+/// Compares signed squared cosines to stay clear of square roots, bisecting over the
+/// angle since the cosine is monotonic over 0-180 degrees.
+fn tilt(gravity: &[i16; 3], reference: &[i16; 3]) -> f32 {
+    let norms = dot(gravity, gravity) * dot(reference, reference);
+    if norms == 0.0 {
+        return 0.0;
+    }
+    let d = dot(gravity, reference);
+    let target = d * abs(d) / norms;
+
+    let (mut low, mut high) = (0.0, 180.0);
+    for _ in 0..16 {
+        let mid = (low + high) / 2.0;
+        let c = cos(mid);
+        if c * abs(c) > target {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Watches the LSM303AGR accelerometer on the internal I2C bus for a pot knocked over,
+/// moved or tilted.
+///
+/// The accelerometer raises its interrupt line on any movement, high-pass filtered so
+/// gravity itself doesn't count. Movement raises the MOVED alert right away. Once the
+/// pot has kept still for a while the alert clears, and its orientation is compared with
+/// the one at boot, raising the critical TIPPED alert while it is tilted beyond the
+/// limit. The pot must therefore be standing upright when the device starts.
+#[rustfmt::skip]
+pub struct Motion<M>
+where
+    M: Actor<Message<'static> = Command> + 'static,
+{
+    i2c: Twim<'static, TWISPI1>,
+    interrupt: PortInput<'static, P0_25>,
+    limits: MotionLimits,
+    reference: [i16; 3],
+    moved: bool,
+    tipped: bool,
+    monitor: Option<Address<'static, M>>,
+}
+
+#[rustfmt::skip]
+impl<M> Motion<M>
+where
+    M: Actor<Message<'static> = Command> + 'static,
+{
+    pub fn new(
+        i2c: Twim<'static, TWISPI1>,
+        interrupt: PortInput<'static, P0_25>,
+        limits: MotionLimits,
+    ) -> Self {
+        Self {
+            i2c,
+            interrupt,
+            limits,
+            reference: [0; 3],
+            moved: false,
+            tipped: false,
+            monitor: None,
+        }
+    }
+
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), twim::Error> {
+        self.i2c.write(ADDRESS, &[register, value]).await
+    }
+
+    async fn read(&mut self, register: u8) -> Result<u8, twim::Error> {
+        let mut data = [0; 1];
+        self.i2c.write_read(ADDRESS, &[register], &mut data).await?;
+        Ok(data[0])
+    }
+
+    async fn configure(&mut self) -> Result<bool, twim::Error> {
+        if self.read(REG_WHO_AM_I).await? != WHO_AM_I {
+            return Ok(false);
+        }
+        let threshold = core::cmp::min(127, self.limits.threshold / 16) as u8;
+        for (register, value) in [
+            // 50 Hz, all axes.
+            (REG_CTRL_REG1, 0b0100_0111),
+            // High-pass filter on the interrupt, leaving out gravity.
+            (REG_CTRL_REG2, 0b0000_0001),
+            // Interrupt 1 on the INT1 pin.
+            (REG_CTRL_REG3, 0b0100_0000),
+            // +-2 g in high resolution mode, 1 mg per digit.
+            (REG_CTRL_REG4, 0b0000_1000),
+            // Latch interrupt 1 until its source is read.
+            (REG_CTRL_REG5, 0b0000_1000),
+            // Active low, as the line is shared and pulled up.
+            (REG_CTRL_REG6, 0b0000_0010),
+            (REG_INT1_THS, threshold),
+            // Any axis beyond the threshold.
+            (REG_INT1_CFG, 0b0010_1010),
+        ]
+        .iter()
+        {
+            self.write(*register, *value).await?;
+        }
+        // Start the high-pass filter from the current acceleration.
+        self.read(REG_REFERENCE).await?;
+        Timer::after(Duration::from_millis(100)).await;
+        self.reference = self.acceleration().await?;
+        self.read(REG_INT1_SRC).await?;
+        Ok(true)
+    }
+
+    /// Acceleration along each axis in mg.
+    async fn acceleration(&mut self) -> Result<[i16; 3], twim::Error> {
+        let mut data = [0; 6];
+        self.i2c
+            .write_read(ADDRESS, &[REG_OUT_X_L | AUTO_INCREMENT], &mut data)
+            .await?;
+        // 12 bit values, left aligned.
+        let axis = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]) >> 4;
+        Ok([axis(0), axis(2), axis(4)])
+    }
+
+    fn alert(&self, rule: &'static str, severity: Severity, active: bool, value: f32) {
+        let alert = Alert {
+            rule,
+            severity,
+            active,
+            value,
+        };
+        self.monitor.unwrap().notify(Command::Alert(alert)).ok();
+    }
+
+    /// Wait for the pot to keep still, then check whether it was tipped over.
+    async fn settle(&mut self) -> Result<(), twim::Error> {
+        loop {
+            // Reading the source releases the interrupt line.
+            self.read(REG_INT1_SRC).await?;
+            let moving = self.interrupt.wait_for_low();
+            let timeout = Timer::after(SETTLE);
+            pin_mut!(moving, timeout);
+            if let Either::Right(_) = select(moving, timeout).await {
+                break;
+            }
+        }
+
+        let gravity = self.acceleration().await?;
+        let tilt = tilt(&gravity, &self.reference);
+        log::info!("Pot settled at {} degrees tilt", tilt);
+        let tipped = tilt > self.limits.max_tilt as f32;
+        if tipped != self.tipped {
+            self.tipped = tipped;
+            self.alert("TIPPED", Severity::Critical, tipped, tilt);
+        }
+        if self.moved {
+            self.moved = false;
+            self.alert("MOVED", Severity::Warning, false, tilt);
+        }
+        Ok(())
+    }
+}
+
+#[rustfmt::skip]
+impl<M> Actor for Motion<M>
+where
+    M: Actor<Message<'static> = Command> + 'static,
+{
+    type Configuration = Address<'static, M>;
+
+    type Message<'m> = ();
+    type OnStartFuture<'m> = impl Future<Output = ()> + 'm;
+    type OnMessageFuture<'m> = impl Future<Output = ()> + 'm;
+
+    fn on_mount(&mut self, _: Address<'static, Self>, config: Self::Configuration) {
+        self.monitor.replace(config);
+    }
+
+    fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
+        async move {
+            let this = unsafe { self.get_unchecked_mut() };
+            match this.configure().await {
+                Ok(true) => {}
+                Ok(false) => {
+                    log::warn!("No LSM303AGR accelerometer found");
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("Error configuring accelerometer: {:?}", e);
+                    return;
+                }
+            }
+            loop {
+                this.interrupt.wait_for_low().await;
+                if !this.moved {
+                    this.moved = true;
+                    this.alert("MOVED", Severity::Warning, true, 0.0);
+                }
+                if let Err(e) = this.settle().await {
+                    log::warn!("Error reading accelerometer: {:?}", e);
+                    // Keep the line from staying low with the interrupt latched.
+                    Timer::after(SETTLE).await;
+                }
+            }
+        }
+    }
+
+    fn on_message<'m>(
+        self: Pin<&'m mut Self>,
+        _: Self::Message<'m>,
+    ) -> Self::OnMessageFuture<'m> {
+        async move {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_near;
+
+    fn assert_tilt(gravity: [i16; 3], reference: [i16; 3], degrees: f32) {
+        // The cosine approximation is off by up to 0.002, a fraction of a degree.
+        assert_near(tilt(&gravity, &reference), degrees, 0.25);
+    }
+
+    #[test]
+    fn cosine() {
+        for (degrees, expected) in [(0.0, 1.0), (45.0, 0.70711), (90.0, 0.0), (180.0, -1.0)].iter()
+        {
+            assert_near(cos(*degrees), *expected, 0.002);
+        }
+    }
+
+    #[test]
+    fn upright() {
+        assert_tilt([0, 0, 1000], [0, 0, 1000], 0.0);
+        assert_tilt([0, 0, 500], [0, 0, 1000], 0.0);
+    }
+
+    #[test]
+    fn leaning() {
+        assert_tilt([0, 707, 707], [0, 0, 1000], 45.0);
+        assert_tilt([-707, 0, 707], [0, 0, 1000], 45.0);
+    }
+
+    #[test]
+    fn on_its_side() {
+        assert_tilt([1000, 0, 0], [0, 0, 1000], 90.0);
+        assert_tilt([0, -1000, 0], [0, 0, 1000], 90.0);
+    }
+
+    #[test]
+    fn upside_down() {
+        assert_tilt([0, 0, -1000], [0, 0, 1000], 180.0);
+    }
+
+    #[test]
+    fn zero_length() {
+        assert_tilt([0, 0, 0], [0, 0, 1000], 0.0);
+        assert_tilt([0, 0, 1000], [0, 0, 0], 0.0);
+    }
+}
@@ -1,15 +1,24 @@
-use crate::plant_monitor::Measurement;
-use core::{future::Future, marker::PhantomData};
+use crate::display::{DisplayActor, DisplayCommand};
+use crate::http::*;
+use crate::icon::Status;
+use crate::plant_monitor::{Event, Measurement};
+use crate::schedule::Settings;
+use crate::trust::PinnedKey;
+use core::{fmt::Write, future::Future, marker::PhantomData};
 
 use core::pin::Pin;
 use drogue_device::{
-    clients::http::*,
     traits::{ip::*, tcp::*},
     *,
 };
 
+use heapless::{consts, String};
 use serde::Serialize;
-use serde_json_core::ser::to_slice;
+use serde_json_core::{de::from_slice, ser::to_slice};
+
+const PATH: &str = "/v1/foo?data_schema=urn:no:lulf:plantmonitor";
+const ALERT_PATH: &str = "/v1/alert?data_schema=urn:no:lulf:plantmonitor:alert";
+const SUMMARY_PATH: &str = "/v1/summary?data_schema=urn:no:lulf:plantmonitor:summary";
 
 pub struct NetworkEndpoint<A, M>
 where
@@ -18,9 +27,11 @@ where
 {
     ip: IpAddress,
     port: u16,
-    username: &'static str,
-    password: &'static str,
-    socket: Option<A>,
+    host: &'static str,
+    credentials: Credentials,
+    verifier: Option<&'static PinnedKey>,
+    client: Option<HttpClient<A>>,
+    display: Option<Address<'static, DisplayActor>>,
     _conv: core::marker::PhantomData<M>,
 }
 
@@ -29,13 +40,21 @@ where
     A: TcpSocket + 'static,
     M: From<Measurement> + Serialize + 'static,
 {
-    pub fn new(ip: IpAddress, port: u16, username: &'static str, password: &'static str) -> Self {
+    pub fn new(
+        ip: IpAddress,
+        port: u16,
+        host: &'static str,
+        credentials: Credentials,
+        verifier: Option<&'static PinnedKey>,
+    ) -> Self {
         Self {
             ip,
             port,
-            username,
-            password,
-            socket: None,
+            host,
+            credentials,
+            verifier,
+            client: None,
+            display: None,
             _conv: PhantomData,
         }
     }
@@ -46,14 +65,22 @@ where
     A: TcpSocket + 'static,
     M: From<Measurement> + Serialize + 'static,
 {
-    type Configuration = A;
+    type Configuration = (A, Address<'static, DisplayActor>);
 
-    type Message<'m> = Measurement;
+    type Message<'m> = Event;
     type OnStartFuture<'m> = impl Future<Output = ()> + 'm;
     type OnMessageFuture<'m> = impl Future<Output = ()> + 'm;
 
     fn on_mount(&mut self, _: Address<'static, Self>, config: Self::Configuration) {
-        self.socket.replace(config);
+        self.display.replace(config.1);
+        self.client.replace(HttpClient::new(
+            config.0,
+            self.ip,
+            self.port,
+            self.host,
+            self.credentials,
+            self.verifier,
+        ));
     }
 
     fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
@@ -66,41 +93,69 @@ where
     ) -> Self::OnMessageFuture<'m> {
         async move {
             let this = unsafe { self.get_unchecked_mut() };
-            if let Some(mut socket) = this.socket.take() {
-                let data: M = message.into();
-                let mut buf = [0; 256];
-                match to_slice(&data, &mut buf) {
+            if let Some(client) = this.client.as_mut() {
+                let mut path: String<consts::U128> = String::new();
+                // Large enough for a summary of every metric.
+                let mut buf = [0; 512];
+                let (serialized, forwarded) = match message {
+                    Event::Measurement(measurement) => {
+                        // Measurements forwarded on behalf of other devices are published as them.
+                        match measurement.device {
+                            Some(device) => {
+                                write!(path, "{}&as={}", PATH, device.as_str()).unwrap()
+                            }
+                            None => path.push_str(PATH).unwrap(),
+                        }
+                        let data: M = measurement.into();
+                        (to_slice(&data, &mut buf), measurement.device.is_some())
+                    }
+                    Event::Alert(alert) => {
+                        path.push_str(ALERT_PATH).unwrap();
+                        (to_slice(&alert, &mut buf), false)
+                    }
+                    Event::Summary(summary) => {
+                        path.push_str(SUMMARY_PATH).unwrap();
+                        (to_slice(&summary, &mut buf), false)
+                    }
+                };
+                match serialized {
                     Ok(size) => {
-                        let mut client = HttpClient::new(
-                            &mut socket,
-                            this.ip,
-                            this.port,
-                            this.username,
-                            this.password,
-                        );
-                        let mut rx_buf = [0; 32];
+                        let mut rx_buf = [0; 64];
                         let result = client
-                            .post(
-                                "/v1/foo?data_schema=urn:no:lulf:plantmonitor",
-                                &buf[..size],
-                                "application/json",
-                                &mut rx_buf[..],
-                            )
+                            .post(&path, &buf[..size], "application/json", &mut rx_buf[..])
                             .await;
+                        let status = if result.is_ok() {
+                            Status::UploadOk
+                        } else {
+                            Status::UploadFailed
+                        };
+                        this.display
+                            .unwrap()
+                            .notify(DisplayCommand::Status(status))
+                            .ok();
                         match result {
-                            Ok(_) => {
-                                log::debug!("Measurement reported");
+                            Ok(len) => {
+                                log::debug!("Event reported");
+                                // Settings in the response are meant for this device.
+                                if len > 0 && !forwarded {
+                                    match from_slice::<Settings>(&rx_buf[..len]) {
+                                        Ok((settings, _)) => settings.apply(),
+                                        Err(e) => log::warn!("Error parsing settings: {:?}", e),
+                                    }
+                                }
+                            }
+                            Err(HttpError::Tls(e)) => {
+                                log::error!("Server rejected ({:?}), event not reported", e);
                             }
                             Err(e) => {
-                                log::warn!("Error reporting measurement: {:?}", e);
+                                log::warn!("Error reporting event: {:?}", e);
                             }
                         }
                     }
                     Err(e) => {
-                        log::warn!("Error serializing measurement: {:?}", e);
+                        log::warn!("Error serializing event: {:?}", e);
                     }
                 }
-                this.socket.replace(socket);
             } else {
                 log::warn!("Socket not bound, skipping sending report");
             }
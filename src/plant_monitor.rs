@@ -1,111 +1,401 @@
-use super::dht11::{self, Delay};
+use super::climate::{self, ClimateSensor};
+use super::clock;
+use super::dht11::Delay;
+use super::display::{DisplayActor, DisplayCommand};
+use super::flow;
+use super::icon::Status;
+use super::light::{DailyLightIntegral, LightSensor};
+use super::metrics;
+use super::rules::{Alert, Metric, Rules, Severity};
+use super::schedule::Schedule;
+use super::soil::SoilSensor;
+use super::sound::{SoundCommand, Speaker};
+use super::stats::{self, Statistics, Summary};
+use super::temp::Temp;
+use super::watering::{self, Watering};
 use core::future::Future;
 
 use core::pin::Pin;
-use drogue_device::{
-    actors::button::{ButtonEvent, FromButtonEvent},
-    *,
-};
+use drogue_device::*;
+use embassy::time::{Duration, Instant};
 use embassy_nrf::{
     gpio::FlexPin,
-    peripherals::{P0_02, P0_04},
+    peripherals::{P0_02, P0_04, TWISPI0},
     saadc::*,
+    twim::Twim,
 };
+use heapless::{consts, Vec};
 use serde::Serialize;
 
 #[derive(Clone, Copy)]
 pub enum Command {
     TakeMeasurement,
-}
-
-#[rustfmt::skip]
-impl<'a, A, D> FromButtonEvent<Command> for PlantMonitor<'a, A, D>
-where
-    A: Actor<Message<'a> = Measurement> + 'static,
-    D: Delay + 'a,
-{
-    fn from(event: ButtonEvent) -> Option<Command> {
-        match event {
-            ButtonEvent::Pressed => None,
-            ButtonEvent::Released => Some(Command::TakeMeasurement),
-        }
-    }
+    /// Take a measurement if one is due according to the schedule.
+    Tick,
+    /// Silence the dry soil alarm for a while.
+    Snooze,
+    /// Sample the soil for calibration, first with the probe in dry soil and then in wet soil.
+    Calibrate,
+    /// Report an alert raised outside of the measurements, such as the pot tipping over.
+    Alert(Alert),
+    /// Log the statistics of the last hour and 24 hours, and show those of the last
+    /// 24 hours.
+    ShowStatistics,
 }
 
 #[rustfmt::skip]
 pub struct PlantMonitor<'a, A, D>
 where
-    A: Actor<Message<'a> = Measurement> + 'static,
+    A: Actor<Message<'a> = Event> + 'static,
     D: Delay + 'static,
 {
     delay: D,
+    climate: ClimateSensor,
     temperature: FlexPin<'a, P0_02>,
     soil: P0_04,
+    soil_sensor: SoilSensor,
+    /// Whether the last soil reading succeeded.
+    has_soil: bool,
+    /// Whether the last measurement has a temperature, read or from the fallback.
+    has_temperature: bool,
+    calibration: SoilCalibration,
+    /// Dry sample taken in the first calibration step.
+    calibrating: Option<i16>,
+    thirsty_below: u8,
+    rules: Rules,
+    schedule: Schedule,
+    next_at: Instant,
     adc: OneShot<'a>,
+    i2c: Twim<'a, TWISPI0>,
+    light: Option<LightSensor>,
+    board: Option<Temp>,
+    /// Offset added to the board temperature to stand in for a faulty climate sensor.
+    fallback_offset: Option<f32>,
+    /// Leaf temperature relative to the air, for the vapour pressure deficit.
+    leaf_offset: f32,
+    dli: DailyLightIntegral,
+    statistics: Statistics,
+    /// Whether the statistics are kept in local time rather than uptime.
+    clock_set: bool,
+    utc_offset: i32,
     sink: Option<Address<'a, A>>,
+    display: Option<Address<'a, DisplayActor>>,
+    speaker: Option<Address<'a, Speaker>>,
+    watering: Option<Address<'a, Watering<'a, A>>>,
 }
 
 #[rustfmt::skip]
 impl<'a, A, D> PlantMonitor<'a, A, D>
 where
-    A: Actor<Message<'a> = Measurement> + 'static,
+    A: Actor<Message<'a> = Event> + 'static,
     D: Delay + 'a,
 {
-    pub fn new(temperature: FlexPin<'a, P0_02>, soil: P0_04, adc: OneShot<'a>, delay: D) -> Self {
+    pub fn new(
+        climate: ClimateSensor,
+        temperature: FlexPin<'a, P0_02>,
+        soil: P0_04,
+        soil_sensor: SoilSensor,
+        calibration: SoilCalibration,
+        thirsty_below: u8,
+        rules: Rules,
+        schedule: Schedule,
+        adc: OneShot<'a>,
+        i2c: Twim<'a, TWISPI0>,
+        light: Option<LightSensor>,
+        board: Option<Temp>,
+        fallback_offset: Option<f32>,
+        leaf_offset: f32,
+        utc_offset: i32,
+        delay: D,
+    ) -> Self {
         Self {
             sink: None,
+            display: None,
+            speaker: None,
+            watering: None,
+            thirsty_below,
+            rules,
+            schedule,
+            next_at: Instant::from_ticks(0),
             delay,
+            climate,
             temperature,
             soil,
+            soil_sensor,
+            has_soil: false,
+            has_temperature: false,
+            calibration,
+            calibrating: None,
             adc,
+            i2c,
+            light,
+            board,
+            fallback_offset,
+            leaf_offset,
+            dli: DailyLightIntegral::new(),
+            statistics: Statistics::new(),
+            clock_set: false,
+            utc_offset,
+        }
+    }
+
+    fn report_status(&self, status: Status) {
+        self.display.unwrap().notify(DisplayCommand::Status(status)).ok();
+    }
+
+    /// Show the status while the condition lasts, and clear it once it is over.
+    fn report_condition(&self, status: Status, active: bool) {
+        let command = if active {
+            DisplayCommand::Status(status)
+        } else {
+            DisplayCommand::ClearStatus(status)
+        };
+        self.display.unwrap().notify(command).ok();
+    }
+
+    async fn report(&mut self, event: Event) {
+        self.sink.unwrap().request(event).unwrap().await;
+    }
+
+    /// Local time for the statistics, falling back to uptime until the clock is set.
+    fn local_time(&mut self) -> u32 {
+        match clock::local_time(self.utc_offset) {
+            Some(time) => {
+                // Start over once the clock is set, the uptime doesn't tell the day.
+                if !self.clock_set {
+                    self.clock_set = true;
+                    self.statistics = Statistics::new();
+                }
+                time
+            }
+            None => Instant::now().as_secs() as u32,
         }
     }
 
-    async fn report_measurement<'m>(&mut self, measurement: Measurement)
-    {
-        self.sink.unwrap().request(measurement).unwrap().await;
+    fn show_statistics(&mut self) {
+        let time = self.local_time();
+        for metric in stats::METRICS.iter() {
+            log::info!(
+                "{:?} last hour: {:?}, last 24 hours: {:?}",
+                metric,
+                self.statistics.last_hour(*metric, time),
+                self.statistics.last_day(*metric, time)
+            );
+        }
+        let summary = self.statistics.summary(time);
+        self.display.unwrap().notify(DisplayCommand::LastDay(summary)).ok();
+    }
+
+    /// Take and report a measurement, scheduling the next one.
+    async fn measure(&mut self) {
+        let (measurement, alerts, summary) = self.take_measurement().await;
+        // Failed readings are left out rather than taken for sudden changes.
+        let interval = self.schedule.next(
+            Some(measurement.moisture).filter(|_| self.has_soil),
+            Some(measurement.temperature).filter(|_| self.has_temperature),
+            self.thirsty_below,
+        );
+        log::debug!("Next measurement in {} s", interval);
+        self.next_at = Instant::now() + Duration::from_secs(interval as u64);
+        // Readings taken while watering are ignored until the water has soaked in.
+        if self.has_soil && !watering::is_busy() {
+            self.watering.unwrap().notify(measurement).ok();
+        }
+        self.report(Event::Measurement(measurement)).await;
+        for alert in alerts {
+            self.report(Event::Alert(alert)).await;
+        }
+        if let Some(summary) = summary {
+            log::info!("Summary of day {}", summary.day);
+            self.report(Event::Summary(summary)).await;
+        }
     }
 
-    async fn take_measurement(&mut self) -> Measurement {
+    async fn calibrate(&mut self) {
+        let sample = match self
+            .soil_sensor
+            .read(&mut self.adc, &mut self.soil, &mut self.i2c)
+            .await
+        {
+            Ok(reading) => reading.sample,
+            Err(e) => {
+                log::warn!("Error getting soil reading for calibration: {:?}", e);
+                self.report_status(Status::SensorFault);
+                return;
+            }
+        };
+        match self.calibrating.take() {
+            None => {
+                log::info!("Calibrating dry soil sample: {}", sample);
+                self.calibrating.replace(sample);
+                self.report_status(Status::Calibrating);
+            }
+            Some(dry) if dry == sample => {
+                log::warn!("Calibration failed, wet and dry soil samples are the same");
+                self.report_status(Status::SensorFault);
+            }
+            Some(dry) => {
+                log::info!("Calibrated soil samples dry: {}, wet: {}", dry, sample);
+                self.calibration = SoilCalibration { dry, wet: sample };
+                self.report_condition(Status::Calibrating, false);
+            }
+        }
+    }
+
+    /// Take a measurement, returning it along with the alerts it raised or cleared, and
+    /// the summary of the previous day once it is the first of a new day.
+    async fn take_measurement(
+        &mut self,
+    ) -> (Measurement, Vec<Alert, consts::U8>, Option<Summary>) {
         let mut measurement = Measurement {
             temperature: 0.0,
             humidity: 0.0,
+            pressure: None,
+            temperature_fallback: false,
+            board_temperature: None,
+            dew_point: None,
+            absolute_humidity: None,
+            vpd: None,
             soil: 0,
+            moisture: 0,
+            soil_temperature: None,
+            lux: None,
+            dli: None,
+            water: flow::delivered(),
+            device: None,
         };
 
-        let delay = &mut self.delay;
+        let mut has_climate = false;
+        let mut fault = false;
 
         log::info!("Take temperature measurement");
-        match dht11::read(delay, &mut self.temperature) {
-            Ok(dht11::Reading {
+        match self
+            .climate
+            .read(&mut self.delay, &mut self.temperature, &mut self.i2c)
+            .await
+        {
+            Ok(climate::Reading {
                 temperature,
-                relative_humidity,
+                humidity,
+                pressure,
             }) => {
-                log::info!(
-                    "Got temperature: {}. Humidity: {}",
-                    temperature,
-                    relative_humidity,
-                );
+                log::info!("Got temperature: {}. Humidity: {}", temperature, humidity);
                 measurement.temperature = temperature;
-                measurement.humidity = relative_humidity;
+                measurement.humidity = humidity;
+                measurement.pressure = pressure;
+                measurement.dew_point = metrics::dew_point(temperature, humidity);
+                measurement
+                    .absolute_humidity
+                    .replace(metrics::absolute_humidity(temperature, humidity));
+                measurement
+                    .vpd
+                    .replace(metrics::vpd(temperature, humidity, self.leaf_offset));
+                has_climate = true;
+            }
+            Err(e) => {
+                log::warn!("Error getting temperature reading: {:?}", e);
+                fault = true;
+            }
+        }
+
+        if let Some(board) = self.board.as_mut() {
+            let temperature = board.read().await;
+            log::info!("Got board temperature: {}", temperature);
+            measurement.board_temperature.replace(temperature);
+            if let (false, Some(offset)) = (has_climate, self.fallback_offset) {
+                measurement.temperature = temperature + offset;
+                measurement.temperature_fallback = true;
             }
-            Err(e) => log::warn!("Error getting temperature reading: {:?}", e),
         }
 
-        let sample = Pin::new(&mut self.adc).sample(&mut self.soil).await;
-        log::info!("Got soil sample: {}", sample);
-        measurement.soil = sample;
-        measurement
+        match self
+            .soil_sensor
+            .read(&mut self.adc, &mut self.soil, &mut self.i2c)
+            .await
+        {
+            Ok(reading) => {
+                log::info!("Got soil sample: {}", reading.sample);
+                measurement.soil = reading.sample;
+                measurement.moisture = self.calibration.moisture(reading.sample);
+                measurement.soil_temperature = reading.temperature;
+                self.has_soil = true;
+                self.report_condition(Status::Thirsty, measurement.moisture < self.thirsty_below);
+            }
+            Err(e) => {
+                log::warn!("Error getting soil reading: {:?}", e);
+                fault = true;
+                self.has_soil = false;
+            }
+        }
+
+        if let Some(light) = self.light {
+            match light.read(&mut self.i2c).await {
+                Ok(lux) => {
+                    log::info!("Got light: {} lx", lux);
+                    // Fall back to uptime until the clock is set, the integral for the
+                    // day starts over once it is.
+                    let uptime = Instant::now().as_secs() as u32;
+                    let (now, day) = match (clock::now(), clock::local_day(self.utc_offset)) {
+                        (Some(now), Some(day)) => (now, day),
+                        _ => (uptime, uptime / clock::SECONDS_PER_DAY),
+                    };
+                    measurement.lux.replace(lux);
+                    measurement.dli.replace(self.dli.add(now, day, lux));
+                }
+                Err(e) => {
+                    log::warn!("Error getting light reading: {:?}", e);
+                    fault = true;
+                }
+            }
+        }
+
+        self.report_condition(Status::SensorFault, fault);
+        self.has_temperature = has_climate || measurement.temperature_fallback;
+
+        let has_soil = self.has_soil;
+        let reading = |metric| match metric {
+            Metric::Moisture if has_soil => Some(measurement.moisture as f32),
+            Metric::Temperature if has_climate || measurement.temperature_fallback => {
+                Some(measurement.temperature)
+            }
+            Metric::Humidity if has_climate => Some(measurement.humidity),
+            Metric::DewPoint => measurement.dew_point,
+            Metric::AbsoluteHumidity => measurement.absolute_humidity,
+            Metric::Vpd => measurement.vpd,
+            _ => None,
+        };
+        let alerts = self
+            .rules
+            .evaluate(&reading, Instant::now().as_secs() as u32);
+        let time = self.local_time();
+        let summary = self.statistics.add(&reading, time);
+        for alert in alerts.iter() {
+            if alert.active {
+                log::warn!("Alert raised: {} ({:?})", alert.rule, alert.severity);
+            } else {
+                log::info!("Alert cleared: {}", alert.rule);
+            }
+        }
+        // Keep sounding the alarm until the plant gets attention.
+        if self.rules.is_active(Severity::Critical) {
+            self.speaker.unwrap().notify(SoundCommand::Alarm).ok();
+        }
+        (measurement, alerts, summary)
     }
 }
 
 #[rustfmt::skip]
 impl<'a, A, D> Actor for PlantMonitor<'a, A, D>
 where
-    A: Actor<Message<'a> = Measurement> + 'a,
+    A: Actor<Message<'a> = Event> + 'a,
     D: Delay + 'static,
 {
-    type Configuration = Address<'a, A>;
+    type Configuration = (
+        Address<'a, A>,
+        Address<'a, DisplayActor>,
+        Address<'a, Speaker>,
+        Address<'a, Watering<'a, A>>,
+    );
     #[rustfmt::skip]
     type Message<'m> where 'a: 'm = Command;
     #[rustfmt::skip]
@@ -114,7 +404,10 @@ where
     type OnMessageFuture<'m> where 'a: 'm = impl Future<Output = ()> + 'm;
 
     fn on_mount(&mut self, _: Address<'static, Self>, config: Self::Configuration) {
-        self.sink.replace(config);
+        self.sink.replace(config.0);
+        self.display.replace(config.1);
+        self.speaker.replace(config.2);
+        self.watering.replace(config.3);
     }
 
     fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
@@ -129,17 +422,153 @@ where
             let this = unsafe { self.get_unchecked_mut() };
             match message {
                 Command::TakeMeasurement => {
-                    let measurement = this.take_measurement().await;
-                    this.report_measurement(measurement).await;
+                    this.measure().await;
+                }
+                Command::Tick => {
+                    // Check how far watering got as soon as the water has soaked in.
+                    if watering::take_due() {
+                        this.next_at = Instant::now();
+                    }
+                    // Start over with the new settings right away.
+                    if this.schedule.reconfigure() {
+                        this.next_at = Instant::now();
+                    }
+                    if Instant::now() >= this.next_at {
+                        this.measure().await;
+                    }
+                }
+                Command::Snooze => {
+                    this.speaker.unwrap().notify(SoundCommand::Snooze).ok();
+                }
+                Command::Calibrate => {
+                    this.calibrate().await;
+                }
+                Command::ShowStatistics => {
+                    this.show_statistics();
+                }
+                Command::Alert(alert) => {
+                    if alert.active && alert.severity == Severity::Critical {
+                        this.speaker.unwrap().notify(SoundCommand::Alarm).ok();
+                    }
+                    this.report(Event::Alert(alert)).await;
                 }
             }
         }
     }
 }
 
+/// Events reported by the monitor.
+#[derive(Clone, Copy)]
+pub enum Event {
+    Measurement(Measurement),
+    Alert(Alert),
+    /// Statistics of the previous day.
+    Summary(Summary),
+}
+
 #[derive(Serialize, Clone, Copy)]
 pub struct Measurement {
     pub soil: i16,
+    /// Soil moisture in percent, derived from the soil sample.
+    pub moisture: u8,
+    /// Soil temperature in °C, if the soil sensor measures it.
+    pub soil_temperature: Option<f32>,
     pub temperature: f32,
     pub humidity: f32,
+    /// Air pressure in hPa, if the climate sensor measures it.
+    pub pressure: Option<f32>,
+    /// Whether the temperature is the board temperature standing in for a faulty
+    /// climate sensor.
+    pub temperature_fallback: bool,
+    /// Die temperature of the nRF52833 in °C.
+    pub board_temperature: Option<f32>,
+    /// Dew point in °C, derived from the temperature and humidity.
+    pub dew_point: Option<f32>,
+    /// Absolute humidity in g/m³, derived from the temperature and humidity.
+    pub absolute_humidity: Option<f32>,
+    /// Vapour pressure deficit in kPa, derived from the temperature and humidity.
+    pub vpd: Option<f32>,
+    /// Illuminance in lux, if a light sensor is fitted.
+    pub lux: Option<f32>,
+    /// Daily light integral so far today in mol/m².
+    pub dli: Option<f32>,
+    /// Water delivered since boot in millilitres, if a flow meter is fitted.
+    pub water: Option<u32>,
+    /// The device the measurement was taken by, if not this one.
+    #[serde(skip)]
+    pub device: Option<DeviceId>,
+}
+
+/// Identity of a device reporting through this one when acting as a gateway.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DeviceId {
+    id: [u8; 20],
+    len: u8,
+}
+
+impl DeviceId {
+    /// Create an identity from a name, returning `None` if it is too long or has
+    /// characters other than ASCII letters, digits, `_` and `-`.
+    ///
+    /// The identity ends up in request paths, so it is kept to characters that need
+    /// no escaping.
+    pub fn new(name: &str) -> Option<Self> {
+        let mut id = [0; 20];
+        if name.is_empty()
+            || name.len() > id.len()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        {
+            return None;
+        }
+        id[..name.len()].copy_from_slice(name.as_bytes());
+        Some(Self {
+            id,
+            len: name.len() as u8,
+        })
+    }
+
+    /// Create an identity from a Bluetooth address, formatted as hex with the
+    /// most significant byte first.
+    pub fn from_address(address: &[u8; 6]) -> Self {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut id = [0; 20];
+        for (i, b) in address.iter().rev().enumerate() {
+            id[i * 2] = HEX[(b >> 4) as usize];
+            id[i * 2 + 1] = HEX[(b & 0xF) as usize];
+        }
+        Self { id, len: 12 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.id[..self.len as usize]) }
+    }
+}
+
+/// Soil samples read with the probe in dry and in wet soil, used to convert samples to
+/// a moisture percentage.
+#[derive(Clone, Copy)]
+pub struct SoilCalibration {
+    pub dry: i16,
+    pub wet: i16,
+}
+
+impl SoilCalibration {
+    /// Convert a sample to moisture in percent, clamped to 0-100 %.
+    pub fn moisture(&self, sample: i16) -> u8 {
+        let range = self.wet as i32 - self.dry as i32;
+        if range == 0 {
+            return 0;
+        }
+        let percent = (sample as i32 - self.dry as i32) * 100 / range;
+        core::cmp::max(0, core::cmp::min(100, percent)) as u8
+    }
+}
+
+impl Default for SoilCalibration {
+    /// Typical readings of the analog probe.
+    fn default() -> Self {
+        SoilSensor::Analog.calibration()
+    }
 }
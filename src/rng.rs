@@ -1,71 +1,334 @@
 // Need our own RNG hal because of rand_core versions being too old in nrf-hal
+use super::drbg::Entropy;
+use core::cell::UnsafeCell;
+use core::num::NonZeroU32;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::Poll;
+use embassy::{interrupt::InterruptExt, util::AtomicWaker};
+use embassy_nrf::interrupt;
+use futures::future::poll_fn;
 use nrf52833_pac::RNG;
-use rand_core::{CryptoRng, RngCore};
 
-pub struct Rng(RNG);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Min-entropy per byte assumed for the bias corrected output of the RNG.
+const ENTROPY_PER_SAMPLE: u32 = 4;
+
+/// Number of identical consecutive samples after which the source is considered
+/// stuck, for a false positive rate of 2^-20 (NIST SP 800-90B, 4.4.1).
+const REPETITION_CUTOFF: u32 = 1 + (20 + ENTROPY_PER_SAMPLE - 1) / ENTROPY_PER_SAMPLE;
+
+/// Window size and cutoff of the adaptive proportion test for 8-bit samples, for a
+/// false positive rate of 2^-20 (NIST SP 800-90B, 4.4.2).
+const PROPORTION_WINDOW: u32 = 512;
+const PROPORTION_CUTOFF: u32 = 62;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RngError {
+    /// The same value was produced too many times in a row.
+    RepetitionCount,
+    /// A value occurred too often within a window of samples.
+    AdaptiveProportion,
+}
+
+impl From<RngError> for rand_core::Error {
+    fn from(error: RngError) -> Self {
+        let code = rand_core::Error::CUSTOM_START + error as u32;
+        rand_core::Error::from(NonZeroU32::new(code).unwrap())
+    }
+}
+
+/// Continuous health tests of the noise source.
+///
+/// Once a test has failed the source is considered broken, and every later sample is
+/// rejected until the device is reset.
+pub struct HealthTests {
+    failure: Option<RngError>,
+    last: u8,
+    repetitions: u32,
+    reference: u8,
+    occurrences: u32,
+    samples: u32,
+}
+
+impl HealthTests {
+    pub const fn new() -> Self {
+        Self {
+            failure: None,
+            last: 0,
+            repetitions: 0,
+            reference: 0,
+            occurrences: 0,
+            samples: PROPORTION_WINDOW,
+        }
+    }
+
+    /// Run the repetition count and adaptive proportion tests on a new sample.
+    pub fn check(&mut self, sample: u8) -> Result<u8, RngError> {
+        if let Some(failure) = self.failure {
+            return Err(failure);
+        }
+
+        if self.repetitions > 0 && sample == self.last {
+            self.repetitions += 1;
+            if self.repetitions >= REPETITION_CUTOFF {
+                self.failure.replace(RngError::RepetitionCount);
+            }
+        } else {
+            self.last = sample;
+            self.repetitions = 1;
+        }
+
+        if self.samples == PROPORTION_WINDOW {
+            self.reference = sample;
+            self.occurrences = 1;
+            self.samples = 1;
+        } else {
+            self.samples += 1;
+            if sample == self.reference {
+                self.occurrences += 1;
+                if self.occurrences >= PROPORTION_CUTOFF {
+                    self.failure.replace(RngError::AdaptiveProportion);
+                }
+            }
+        }
+
+        match self.failure {
+            Some(failure) => Err(failure),
+            None => Ok(sample),
+        }
+    }
+}
+
+pub struct Rng {
+    rng: RNG,
+    health: HealthTests,
+}
 
 impl Rng {
-    pub fn new(rng: RNG) -> Self {
+    pub fn new(rng: RNG, irq: interrupt::RNG) -> Self {
         rng.config.write(|w| w.dercen().enabled());
-        Self(rng)
+        rng.intenclr.write(|w| w.valrdy().clear());
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+        Self {
+            rng,
+            health: HealthTests::new(),
+        }
     }
 
-    /// Fill the provided buffer with random bytes.
-    ///
-    /// Will block until the buffer is full.
-    pub fn random(&mut self, buf: &mut [u8]) {
-        self.0.tasks_start.write(|w| unsafe { w.bits(1) });
+    unsafe fn on_interrupt(_: *mut ()) {
+        // Mask the interrupt until the next byte is requested, the event is cleared once read.
+        let rng = &*RNG::ptr();
+        rng.intenclr.write(|w| w.valrdy().clear());
+        WAKER.wake();
+    }
+
+    /// Fill the provided buffer with random bytes, waiting for the interrupt between
+    /// each of them.
+    pub async fn fill(&mut self, buf: &mut [u8]) -> Result<(), RngError> {
+        self.rng.tasks_start.write(|w| unsafe { w.bits(1) });
 
+        let mut result = Ok(());
         for b in buf {
-            // Wait for random byte to become ready, reset the flag once it is.
-            while self.0.events_valrdy.read().bits() == 0 {}
-            self.0.events_valrdy.write(|w| unsafe { w.bits(0) });
+            let rng = &self.rng;
+            poll_fn(|cx| {
+                WAKER.register(cx.waker());
+                if rng.events_valrdy.read().bits() != 0 {
+                    Poll::Ready(())
+                } else {
+                    rng.intenset.write(|w| w.valrdy().set());
+                    Poll::Pending
+                }
+            })
+            .await;
+            match self.read() {
+                Ok(value) => *b = value,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
 
-            *b = self.0.value.read().value().bits();
+        self.rng.tasks_stop.write(|w| unsafe { w.bits(1) });
+        result
+    }
+
+    /// Read the ready byte and reset the flag, passing it through the health tests.
+    fn read(&mut self) -> Result<u8, RngError> {
+        self.rng.events_valrdy.write(|w| unsafe { w.bits(0) });
+        let value = self.rng.value.read().value().bits();
+        self.health.check(value)
+    }
+}
+
+const SEED_EMPTY: u8 = 0;
+const SEED_READY: u8 = 1;
+const SEED_REPETITION_COUNT: u8 = 2;
+const SEED_ADAPTIVE_PROPORTION: u8 = 3;
+
+/// Seed gathered from the RNG ahead of time, so the DRBG can be reseeded without
+/// waiting for the interrupts.
+///
+/// Holds a single seed, handed over from the seeding task to the DRBG. Once the RNG has
+/// failed its health tests, the failure is handed over instead.
+pub struct Seeds {
+    seed: UnsafeCell<[u8; 32]>,
+    state: AtomicU8,
+    taken: AtomicWaker,
+    filled: AtomicWaker,
+}
+
+// The seed is only written by the seeding task while empty, and only read while ready.
+unsafe impl Sync for Seeds {}
+
+impl Seeds {
+    pub const fn new() -> Self {
+        Self {
+            seed: UnsafeCell::new([0; 32]),
+            state: AtomicU8::new(SEED_EMPTY),
+            taken: AtomicWaker::new(),
+            filled: AtomicWaker::new(),
         }
+    }
 
-        self.0.tasks_stop.write(|w| unsafe { w.bits(1) });
+    /// Wait until a seed is ready, or the RNG has failed.
+    ///
+    /// The DRBG refuses to produce anything before its first seed, so this is awaited
+    /// before anything may ask it for random bytes.
+    pub async fn ready(&self) {
+        poll_fn(|cx| {
+            self.filled.register(cx.waker());
+            if self.state.load(Ordering::Acquire) == SEED_EMPTY {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await
     }
 
-    /// Return a random `u32`.
-    pub fn random_u32(&mut self) -> u32 {
-        let mut buf = [0; 4];
-        self.random(&mut buf);
-        buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+    /// Take the seed if one is ready, without waiting for it.
+    pub fn take(&self) -> Result<Option<[u8; 32]>, RngError> {
+        match self.state.load(Ordering::Acquire) {
+            SEED_EMPTY => Ok(None),
+            SEED_READY => {
+                let seed = unsafe { *self.seed.get() };
+                self.state.store(SEED_EMPTY, Ordering::Release);
+                self.taken.wake();
+                Ok(Some(seed))
+            }
+            SEED_REPETITION_COUNT => Err(RngError::RepetitionCount),
+            _ => Err(RngError::AdaptiveProportion),
+        }
     }
+}
 
-    /// Return a random `u64`.
-    pub fn random_u64(&mut self) -> u64 {
-        let mut buf = [0; 8];
-        self.random(&mut buf);
-        buf[0] as u64
-            | (buf[1] as u64) << 8
-            | (buf[2] as u64) << 16
-            | (buf[3] as u64) << 24
-            | (buf[4] as u64) << 32
-            | (buf[5] as u64) << 40
-            | (buf[6] as u64) << 48
-            | (buf[7] as u64) << 56
+impl Entropy for &'static Seeds {
+    fn seed(&mut self) -> Result<Option<[u8; 32]>, rand_core::Error> {
+        Ok(self.take()?)
     }
 }
 
-impl RngCore for Rng {
-    fn next_u32(&mut self) -> u32 {
-        self.random_u32()
+/// Keep a fresh seed ready, gathering the next one once the last has been taken.
+#[embassy::task]
+pub async fn seed_task(mut rng: Rng, seeds: &'static Seeds) {
+    loop {
+        poll_fn(|cx| {
+            seeds.taken.register(cx.waker());
+            if seeds.state.load(Ordering::Acquire) == SEED_EMPTY {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        let seed = unsafe { &mut *seeds.seed.get() };
+        let state = match rng.fill(seed).await {
+            Ok(()) => SEED_READY,
+            Err(RngError::RepetitionCount) => SEED_REPETITION_COUNT,
+            Err(RngError::AdaptiveProportion) => SEED_ADAPTIVE_PROPORTION,
+        };
+        seeds.state.store(state, Ordering::Release);
+        seeds.filled.wake();
+        if state != SEED_READY {
+            log::error!("Entropy source failed its health tests");
+            return;
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn next_u64(&mut self) -> u64 {
-        self.random_u64()
+    #[test]
+    fn varying_samples_pass() {
+        let mut health = HealthTests::new();
+        for i in 0..10_000u32 {
+            let sample = (i * 7 % 256) as u8;
+            assert_eq!(health.check(sample), Ok(sample));
+        }
     }
 
-    fn fill_bytes(&mut self, dest: &mut [u8]) {
-        self.random(dest)
+    #[test]
+    fn stuck_source() {
+        assert_eq!(REPETITION_CUTOFF, 6);
+        let mut health = HealthTests::new();
+        health.check(1).unwrap();
+        for _ in 0..REPETITION_CUTOFF - 1 {
+            assert_eq!(health.check(0x55), Ok(0x55));
+        }
+        assert_eq!(health.check(0x55), Err(RngError::RepetitionCount));
+        // The failure sticks, whatever comes next.
+        assert_eq!(health.check(0x56), Err(RngError::RepetitionCount));
     }
 
-    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
-        self.fill_bytes(dest);
-        Ok(())
+    #[test]
+    fn repetitions_restart() {
+        let mut health = HealthTests::new();
+        for i in 0..100u8 {
+            for _ in 0..REPETITION_CUTOFF - 1 {
+                health.check(i * 2).unwrap();
+            }
+            health.check(i * 2 + 1).unwrap();
+        }
+    }
+
+    /// A window with the first sample repeated `occurrences` times, every 8 samples, and
+    /// other values that never repeat back to back in between.
+    fn biased_window(occurrences: u32) -> impl Iterator<Item = u8> {
+        (0..PROPORTION_WINDOW).map(move |i| {
+            if i % 8 == 0 && i / 8 < occurrences {
+                0
+            } else {
+                1 + (i % 255) as u8
+            }
+        })
+    }
+
+    #[test]
+    fn biased_source() {
+        let mut health = HealthTests::new();
+        let mut results = biased_window(PROPORTION_CUTOFF).map(|sample| health.check(sample));
+        // Samples until the last occurrence pass.
+        for _ in 0..(PROPORTION_CUTOFF - 1) * 8 {
+            assert!(results.next().unwrap().is_ok());
+        }
+        assert_eq!(results.next(), Some(Err(RngError::AdaptiveProportion)));
+        assert_eq!(results.next(), Some(Err(RngError::AdaptiveProportion)));
     }
-}
 
-impl CryptoRng for Rng {}
+    #[test]
+    fn bias_below_cutoff_passes() {
+        let mut health = HealthTests::new();
+        for _ in 0..4 {
+            for sample in biased_window(PROPORTION_CUTOFF - 1) {
+                assert_eq!(health.check(sample), Ok(sample));
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use heapless::{consts, Vec};
+use serde::Serialize;
+
+type MaxRules = consts::U8;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    /// The plant needs attention, sounding the alarm.
+    Critical,
+}
+
+/// The reading a rule applies to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Metric {
+    /// Soil moisture in percent.
+    Moisture,
+    /// Temperature in °C.
+    Temperature,
+    /// Relative humidity in percent.
+    Humidity,
+    /// Dew point in °C.
+    DewPoint,
+    /// Absolute humidity in g/m³.
+    AbsoluteHumidity,
+    /// Vapour pressure deficit in kPa.
+    Vpd,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Limit {
+    Below(f32),
+    Above(f32),
+}
+
+/// Raises an alert when a reading stays beyond the limit for `debounce` seconds, and
+/// clears it once the reading is back within the limit by at least `hysteresis`.
+pub struct Rule {
+    pub name: &'static str,
+    pub metric: Metric,
+    pub limit: Limit,
+    pub hysteresis: f32,
+    pub debounce: u32,
+    pub severity: Severity,
+}
+
+impl Rule {
+    fn beyond(&self, value: f32) -> bool {
+        match self.limit {
+            Limit::Below(limit) => value < limit,
+            Limit::Above(limit) => value > limit,
+        }
+    }
+
+    fn within(&self, value: f32) -> bool {
+        match self.limit {
+            Limit::Below(limit) => value >= limit + self.hysteresis,
+            Limit::Above(limit) => value <= limit - self.hysteresis,
+        }
+    }
+}
+
+/// An alert raised, or cleared, by a rule.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Alert {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub active: bool,
+    /// The reading that raised or cleared the alert.
+    pub value: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Clear,
+    /// Beyond the limit since the given time, but not for long enough yet.
+    Pending(u32),
+    Active,
+}
+
+/// Evaluates the rules of a plant against its readings, up to 8 rules.
+pub struct Rules {
+    rules: &'static [Rule],
+    states: Vec<State, MaxRules>,
+}
+
+impl Rules {
+    pub fn new(rules: &'static [Rule]) -> Self {
+        let mut states = Vec::new();
+        for _ in rules {
+            if states.push(State::Clear).is_err() {
+                log::warn!("Only the first {} rules are evaluated", states.len());
+                break;
+            }
+        }
+        Self {
+            rules: &rules[..states.len()],
+            states,
+        }
+    }
+
+    /// Evaluate the rules against the readings at `now` seconds, returning the alerts
+    /// raised or cleared. Rules for readings that are not available keep their state.
+    pub fn evaluate<F>(&mut self, reading: F, now: u32) -> Vec<Alert, MaxRules>
+    where
+        F: Fn(Metric) -> Option<f32>,
+    {
+        let mut alerts = Vec::new();
+        for (rule, state) in self.rules.iter().zip(self.states.iter_mut()) {
+            let value = match reading(rule.metric) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let next = match *state {
+                State::Clear | State::Pending(_) if !rule.beyond(value) => State::Clear,
+                State::Clear => State::Pending(now),
+                State::Pending(since) => State::Pending(since),
+                State::Active if rule.within(value) => State::Clear,
+                State::Active => State::Active,
+            };
+            let next = match next {
+                State::Pending(since) if now.wrapping_sub(since) >= rule.debounce => State::Active,
+                next => next,
+            };
+
+            if (next == State::Active) != (*state == State::Active) {
+                alerts
+                    .push(Alert {
+                        rule: rule.name,
+                        severity: rule.severity,
+                        active: next == State::Active,
+                        value,
+                    })
+                    .ok();
+            }
+            *state = next;
+        }
+        alerts
+    }
+
+    /// Whether any alert of the given severity or above is active.
+    pub fn is_active(&self, severity: Severity) -> bool {
+        self.rules
+            .iter()
+            .zip(self.states.iter())
+            .any(|(rule, state)| *state == State::Active && rule.severity >= severity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static DRY: [Rule; 1] = [Rule {
+        name: "DRY",
+        metric: Metric::Moisture,
+        limit: Limit::Below(30.0),
+        hysteresis: 5.0,
+        debounce: 60,
+        severity: Severity::Critical,
+    }];
+
+    static HOT: [Rule; 1] = [Rule {
+        name: "HOT",
+        metric: Metric::Temperature,
+        limit: Limit::Above(30.0),
+        hysteresis: 2.0,
+        debounce: 0,
+        severity: Severity::Warning,
+    }];
+
+    fn moisture(value: f32) -> impl Fn(Metric) -> Option<f32> {
+        move |metric| match metric {
+            Metric::Moisture => Some(value),
+            _ => None,
+        }
+    }
+
+    fn temperature(value: f32) -> impl Fn(Metric) -> Option<f32> {
+        move |metric| match metric {
+            Metric::Temperature => Some(value),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn debounce() {
+        let mut rules = Rules::new(&DRY);
+        assert!(rules.evaluate(moisture(20.0), 0).is_empty());
+        assert!(rules.evaluate(moisture(20.0), 59).is_empty());
+        let alerts = rules.evaluate(moisture(21.0), 60);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "DRY");
+        assert!(alerts[0].active);
+        assert_eq!(alerts[0].value, 21.0);
+        assert!(rules.is_active(Severity::Critical));
+        // Staying beyond the limit doesn't raise the alert again.
+        assert!(rules.evaluate(moisture(20.0), 120).is_empty());
+    }
+
+    #[test]
+    fn debounce_restarts_when_back_within_limit() {
+        let mut rules = Rules::new(&DRY);
+        rules.evaluate(moisture(20.0), 0);
+        rules.evaluate(moisture(31.0), 30);
+        assert!(rules.evaluate(moisture(20.0), 60).is_empty());
+        assert!(rules.evaluate(moisture(20.0), 119).is_empty());
+        assert_eq!(rules.evaluate(moisture(20.0), 120).len(), 1);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut rules = Rules::new(&HOT);
+        assert!(rules.evaluate(temperature(30.0), 0).is_empty());
+        assert!(rules.evaluate(temperature(30.5), 10)[0].active);
+        // Back within the limit, but not by the hysteresis yet.
+        assert!(rules.evaluate(temperature(29.0), 20).is_empty());
+        assert!(rules.evaluate(temperature(28.1), 30).is_empty());
+        assert!(rules.is_active(Severity::Warning));
+        assert!(!rules.is_active(Severity::Critical));
+    }
+
+    #[test]
+    fn clearing() {
+        let mut rules = Rules::new(&HOT);
+        rules.evaluate(temperature(35.0), 0);
+        let alerts = rules.evaluate(temperature(28.0), 10);
+        assert_eq!(alerts.len(), 1);
+        assert!(!alerts[0].active);
+        assert_eq!(alerts[0].value, 28.0);
+        assert!(!rules.is_active(Severity::Info));
+        // Cleared alerts are raised again once the reading goes beyond the limit.
+        assert!(rules.evaluate(temperature(31.0), 20)[0].active);
+    }
+
+    #[test]
+    fn missing_readings_keep_state() {
+        let mut rules = Rules::new(&HOT);
+        rules.evaluate(temperature(35.0), 0);
+        assert!(rules.evaluate(moisture(50.0), 10).is_empty());
+        assert!(rules.is_active(Severity::Warning));
+    }
+}
@@ -0,0 +1,247 @@
+use crate::watering;
+use core::sync::atomic::{AtomicU32, Ordering};
+use serde::Deserialize;
+
+/// Change in soil moisture, in percent, between readings considered fast.
+const MOISTURE_STEP: u8 = 2;
+
+/// Change in temperature, in °C, between readings considered fast.
+const TEMPERATURE_STEP: f32 = 1.0;
+
+/// Distance in percent from the thirsty threshold within which the soil moisture is
+/// considered near it.
+const NEAR_THRESHOLD: u8 = 5;
+
+/// Interval in seconds received from the server, or 0 if unchanged.
+static INTERVAL: AtomicU32 = AtomicU32::new(0);
+
+/// Adaptive mode received from the server: 0 if unchanged, 1 if off and 2 if on.
+static ADAPTIVE: AtomicU32 = AtomicU32::new(0);
+
+/// Settings the server may send in the response body, such as
+/// `{"interval":300,"adaptive":false}`.
+#[derive(Deserialize)]
+pub struct Settings {
+    interval: Option<u32>,
+    adaptive: Option<bool>,
+    /// Clear a watering lockout, see [`Watering`](crate::watering::Watering).
+    resume_watering: Option<bool>,
+}
+
+impl Settings {
+    /// Store the settings for the schedule to pick up before the next measurement.
+    pub fn apply(&self) {
+        if let Some(interval) = self.interval.filter(|i| *i > 0) {
+            INTERVAL.store(interval, Ordering::Relaxed);
+        }
+        if let Some(adaptive) = self.adaptive {
+            ADAPTIVE.store(if adaptive { 2 } else { 1 }, Ordering::Relaxed);
+        }
+        if self.resume_watering == Some(true) {
+            watering::resume();
+        }
+    }
+}
+
+/// Decides how long to wait between measurements, in seconds.
+///
+/// In adaptive mode the interval is halved while the soil moisture or temperature
+/// changes fast or is close to the thirsty threshold, and grows by half again while
+/// readings are stable. Failed readings count as neither changing nor close to the
+/// threshold. Otherwise the configured interval is used. Either way the interval stays
+/// within the minimum and maximum, whatever the server sends.
+pub struct Schedule {
+    interval: u32,
+    min: u32,
+    max: u32,
+    adaptive: bool,
+    current: u32,
+    last_moisture: Option<u8>,
+    last_temperature: Option<f32>,
+}
+
+impl Schedule {
+    pub const fn new(interval: u32, min: u32, max: u32, adaptive: bool) -> Self {
+        Self {
+            interval,
+            min,
+            max,
+            adaptive,
+            current: interval,
+            last_moisture: None,
+            last_temperature: None,
+        }
+    }
+
+    /// Pick up settings received from the server since the last call, returning whether
+    /// they changed the schedule.
+    pub fn reconfigure(&mut self) -> bool {
+        let mut changed = false;
+        match INTERVAL.swap(0, Ordering::Relaxed) {
+            0 => {}
+            interval => {
+                self.interval = self.clamp(interval);
+                log::info!("Measurement interval set to {} s", self.interval);
+                changed = true;
+            }
+        }
+        match ADAPTIVE.swap(0, Ordering::Relaxed) {
+            0 => {}
+            adaptive => {
+                self.adaptive = adaptive == 2;
+                log::info!(
+                    "Adaptive sampling {}",
+                    if self.adaptive { "on" } else { "off" }
+                );
+                changed = true;
+            }
+        }
+        if changed {
+            self.current = self.interval;
+        }
+        changed
+    }
+
+    /// The interval until the measurement after this one, given the readings of this
+    /// one or `None` where they failed.
+    pub fn next(
+        &mut self,
+        moisture: Option<u8>,
+        temperature: Option<f32>,
+        thirsty_below: u8,
+    ) -> u32 {
+        // A missing reading is compared with the last one there was.
+        let moisture_change = match (moisture, self.last_moisture) {
+            (Some(moisture), Some(last)) => (moisture as i16 - last as i16).abs(),
+            _ => 0,
+        };
+        let temperature_change = match (temperature, self.last_temperature) {
+            (Some(temperature), Some(last)) => temperature - last,
+            _ => 0.0,
+        };
+        self.last_moisture = moisture.or(self.last_moisture);
+        self.last_temperature = temperature.or(self.last_temperature);
+        if !self.adaptive {
+            self.current = self.clamp(self.interval);
+            return self.current;
+        }
+
+        let changing = moisture_change >= MOISTURE_STEP as i16
+            || temperature_change >= TEMPERATURE_STEP
+            || temperature_change <= -TEMPERATURE_STEP;
+        let near = match moisture {
+            Some(moisture) => {
+                moisture.saturating_add(NEAR_THRESHOLD) >= thirsty_below
+                    && moisture < thirsty_below.saturating_add(NEAR_THRESHOLD)
+            }
+            None => false,
+        };
+
+        self.current = if changing || near {
+            self.current / 2
+        } else {
+            self.current.saturating_add(self.current / 2)
+        };
+        self.current = self.clamp(self.current);
+        self.current
+    }
+
+    fn clamp(&self, interval: u32) -> u32 {
+        core::cmp::max(self.min, core::cmp::min(self.max, interval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THIRSTY: u8 = 20;
+
+    #[test]
+    fn fixed_interval() {
+        let mut schedule = Schedule::new(300, 60, 3600, false);
+        assert_eq!(schedule.next(Some(50), Some(20.0), THIRSTY), 300);
+        assert_eq!(schedule.next(Some(10), Some(30.0), THIRSTY), 300);
+        assert_eq!(schedule.next(None, None, THIRSTY), 300);
+    }
+
+    #[test]
+    fn halves_while_changing() {
+        let mut schedule = Schedule::new(600, 60, 3600, true);
+        assert_eq!(schedule.next(Some(50), Some(20.0), THIRSTY), 900);
+        assert_eq!(schedule.next(Some(52), Some(20.0), THIRSTY), 450);
+        assert_eq!(schedule.next(Some(52), Some(21.0), THIRSTY), 225);
+        assert_eq!(schedule.next(Some(52), Some(19.5), THIRSTY), 112);
+        assert_eq!(schedule.next(Some(48), Some(19.5), THIRSTY), 60);
+        assert_eq!(schedule.next(Some(40), Some(19.5), THIRSTY), 60);
+        // Small changes count as stable.
+        assert_eq!(schedule.next(Some(41), Some(20.4), THIRSTY), 90);
+    }
+
+    #[test]
+    fn grows_while_stable() {
+        let mut schedule = Schedule::new(600, 60, 3600, true);
+        let intervals: [u32; 6] = [900, 1350, 2025, 3037, 3600, 3600];
+        for interval in intervals.iter() {
+            assert_eq!(schedule.next(Some(50), Some(20.0), THIRSTY), *interval);
+        }
+    }
+
+    #[test]
+    fn halves_near_threshold() {
+        let mut schedule = Schedule::new(600, 60, 3600, true);
+        assert_eq!(schedule.next(Some(24), Some(20.0), THIRSTY), 300);
+        assert_eq!(schedule.next(Some(24), Some(20.0), THIRSTY), 150);
+        assert_eq!(schedule.next(Some(25), Some(20.0), THIRSTY), 225);
+        assert_eq!(schedule.next(Some(16), Some(20.0), THIRSTY), 112);
+        assert_eq!(schedule.next(Some(15), Some(20.0), THIRSTY), 60);
+        assert_eq!(schedule.next(Some(14), Some(20.0), THIRSTY), 90);
+    }
+
+    #[test]
+    fn missing_readings_skipped() {
+        let mut schedule = Schedule::new(600, 60, 3600, true);
+        assert_eq!(schedule.next(Some(50), Some(20.0), THIRSTY), 900);
+        // A failed climate or soil reading is not a sudden change, nor near the threshold.
+        assert_eq!(schedule.next(Some(50), None, THIRSTY), 1350);
+        assert_eq!(schedule.next(None, Some(20.0), THIRSTY), 2025);
+        assert_eq!(schedule.next(None, None, THIRSTY), 3037);
+        // Readings are compared with the last one there was.
+        assert_eq!(schedule.next(Some(50), Some(20.5), THIRSTY), 3600);
+        assert_eq!(schedule.next(Some(50), None, THIRSTY), 3600);
+        assert_eq!(schedule.next(Some(50), Some(22.0), THIRSTY), 1800);
+    }
+
+    #[test]
+    fn settings_clamped() {
+        let mut schedule = Schedule::new(600, 60, 3600, true);
+        Settings {
+            interval: Some(5),
+            adaptive: Some(false),
+            resume_watering: None,
+        }
+        .apply();
+        assert!(schedule.reconfigure());
+        assert!(!schedule.reconfigure());
+        assert_eq!(schedule.next(Some(50), Some(20.0), THIRSTY), 60);
+
+        Settings {
+            interval: Some(100_000),
+            adaptive: None,
+            resume_watering: None,
+        }
+        .apply();
+        assert!(schedule.reconfigure());
+        assert_eq!(schedule.next(Some(50), Some(20.0), THIRSTY), 3600);
+
+        // Adaptive mode starts over from the configured interval.
+        Settings {
+            interval: Some(600),
+            adaptive: Some(true),
+            resume_watering: None,
+        }
+        .apply();
+        assert!(schedule.reconfigure());
+        assert_eq!(schedule.next(Some(50), Some(20.0), THIRSTY), 900);
+    }
+}
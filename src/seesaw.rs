@@ -0,0 +1,76 @@
+use embassy::time::{Duration, Timer};
+use embassy::traits::i2c::I2c;
+
+const STATUS_BASE: u8 = 0x00;
+const STATUS_TEMP: u8 = 0x04;
+const TOUCH_BASE: u8 = 0x0F;
+const TOUCH_CHANNEL_OFFSET: u8 = 0x10;
+
+/// Value read while the touch measurement is not ready yet.
+const NOT_READY: u16 = 0xFFFF;
+
+#[derive(Debug)]
+pub enum SeesawError<E> {
+    I2c(E),
+    /// The touch measurement was not ready after several attempts.
+    NotReady,
+}
+
+/// Adafruit STEMMA soil sensor speaking the seesaw protocol, at addresses 0x36 to 0x39
+/// set by its address jumpers.
+#[derive(Clone, Copy, Debug)]
+pub struct Seesaw {
+    pub address: u8,
+}
+
+impl Seesaw {
+    async fn read_register<I: I2c>(
+        &self,
+        i2c: &mut I,
+        base: u8,
+        function: u8,
+        delay: Duration,
+        data: &mut [u8],
+    ) -> Result<(), I::Error> {
+        i2c.write(self.address, &[base, function]).await?;
+        // The sensor needs time to respond before it can be read.
+        Timer::after(delay).await;
+        i2c.read(self.address, data).await
+    }
+
+    /// Capacitance of the probe read through touch channel 0, higher in wetter soil.
+    pub async fn moisture<I: I2c>(&self, i2c: &mut I) -> Result<u16, SeesawError<I::Error>> {
+        for _ in 0..3 {
+            let mut data = [0; 2];
+            self.read_register(
+                i2c,
+                TOUCH_BASE,
+                TOUCH_CHANNEL_OFFSET,
+                Duration::from_millis(5),
+                &mut data,
+            )
+            .await
+            .map_err(SeesawError::I2c)?;
+            let value = u16::from_be_bytes(data);
+            if value != NOT_READY {
+                return Ok(value);
+            }
+        }
+        Err(SeesawError::NotReady)
+    }
+
+    /// Temperature of the sensor chip in °C.
+    pub async fn temperature<I: I2c>(&self, i2c: &mut I) -> Result<f32, I::Error> {
+        let mut data = [0; 4];
+        self.read_register(
+            i2c,
+            STATUS_BASE,
+            STATUS_TEMP,
+            Duration::from_millis(1),
+            &mut data,
+        )
+        .await?;
+        // 16.16 fixed point, with the two top bits reserved.
+        Ok((u32::from_be_bytes(data) & 0x3FFF_FFFF) as f32 / 65536.0)
+    }
+}
@@ -0,0 +1,127 @@
+use super::climate::{ClimateError, Reading};
+use embassy::time::{Duration, Timer};
+use embassy::traits::i2c::I2c;
+
+const SHT4X_ADDRESS: u8 = 0x44;
+
+/// CRC-8 with polynomial 0x31 and initial value 0xFF protecting each word read from
+/// the sensor, as per the datasheet: `crc8(&[0xBE, 0xEF]) == 0x92`.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xFF;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Check the CRC of the temperature and humidity words, returning their raw values.
+fn words(data: &[u8; 6]) -> Option<(u16, u16)> {
+    if crc8(&data[0..2]) != data[2] || crc8(&data[3..5]) != data[5] {
+        return None;
+    }
+    Some((
+        u16::from_be_bytes([data[0], data[1]]),
+        u16::from_be_bytes([data[3], data[4]]),
+    ))
+}
+
+/// Convert raw SHT3x values to °C and percent.
+pub fn sht3x_convert(temperature: u16, humidity: u16) -> (f32, f32) {
+    (
+        -45.0 + 175.0 * temperature as f32 / 65535.0,
+        100.0 * humidity as f32 / 65535.0,
+    )
+}
+
+/// Convert raw SHT4x values to °C and percent. The humidity formula may go slightly
+/// beyond 0-100 % and is clamped, as the datasheet recommends.
+pub fn sht4x_convert(temperature: u16, humidity: u16) -> (f32, f32) {
+    let rh = -6.0 + 125.0 * humidity as f32 / 65535.0;
+    (
+        -45.0 + 175.0 * temperature as f32 / 65535.0,
+        if rh < 0.0 {
+            0.0
+        } else if rh > 100.0 {
+            100.0
+        } else {
+            rh
+        },
+    )
+}
+
+async fn measure<I: I2c>(
+    i2c: &mut I,
+    address: u8,
+    command: &[u8],
+    duration: Duration,
+) -> Result<(u16, u16), ClimateError<I::Error>> {
+    i2c.write(address, command)
+        .await
+        .map_err(ClimateError::I2c)?;
+    Timer::after(duration).await;
+    let mut data = [0; 6];
+    i2c.read(address, &mut data)
+        .await
+        .map_err(ClimateError::I2c)?;
+    words(&data).ok_or(ClimateError::Crc)
+}
+
+/// Single shot measurement with high repeatability and without clock stretching.
+pub async fn read_sht3x<I: I2c>(
+    i2c: &mut I,
+    address: u8,
+) -> Result<Reading, ClimateError<I::Error>> {
+    let (t, rh) = measure(i2c, address, &[0x24, 0x00], Duration::from_millis(16)).await?;
+    let (temperature, humidity) = sht3x_convert(t, rh);
+    Ok(Reading {
+        temperature,
+        humidity,
+        pressure: None,
+    })
+}
+
+/// Measurement with high precision.
+pub async fn read_sht4x<I: I2c>(i2c: &mut I) -> Result<Reading, ClimateError<I::Error>> {
+    let (t, rh) = measure(i2c, SHT4X_ADDRESS, &[0xFD], Duration::from_millis(10)).await?;
+    let (temperature, humidity) = sht4x_convert(t, rh);
+    Ok(Reading {
+        temperature,
+        humidity,
+        pressure: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc8_datasheet_example() {
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+        assert_eq!(crc8(&[]), 0xFF);
+    }
+
+    #[test]
+    fn words_checked() {
+        let data = [0xBE, 0xEF, 0x92, 0x66, 0x66, crc8(&[0x66, 0x66])];
+        assert_eq!(words(&data), Some((0xBEEF, 0x6666)));
+        let mut corrupt = data;
+        corrupt[4] ^= 0x01;
+        assert_eq!(words(&corrupt), None);
+    }
+
+    #[test]
+    fn convert() {
+        assert_eq!(sht3x_convert(0, 0), (-45.0, 0.0));
+        assert_eq!(sht3x_convert(65535, 65535), (130.0, 100.0));
+        assert_eq!(sht4x_convert(0, 0).1, 0.0);
+        assert_eq!(sht4x_convert(0, 65535).1, 100.0);
+    }
+}
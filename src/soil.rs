@@ -0,0 +1,92 @@
+use super::plant_monitor::SoilCalibration;
+use super::seesaw::{Seesaw, SeesawError};
+use core::pin::Pin;
+use embassy::traits::i2c::I2c;
+use embassy_nrf::{peripherals::P0_04, saadc::OneShot};
+
+#[derive(Clone, Copy)]
+pub struct Reading {
+    /// Raw sample, converted to moisture by the soil calibration.
+    pub sample: i16,
+    /// Soil temperature in °C, for sensors measuring it.
+    pub temperature: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum SoilError<E> {
+    I2c(E),
+    /// The sensor had no measurement ready after several attempts.
+    NotReady,
+    /// No sensors are configured.
+    NoSensors,
+}
+
+impl<E> From<SeesawError<E>> for SoilError<E> {
+    fn from(error: SeesawError<E>) -> Self {
+        match error {
+            SeesawError::I2c(e) => SoilError::I2c(e),
+            SeesawError::NotReady => SoilError::NotReady,
+        }
+    }
+}
+
+/// Sensors measuring the soil moisture.
+#[derive(Clone, Copy)]
+pub enum SoilSensor {
+    /// Analog capacitive probe on P0_04.
+    Analog,
+    /// Seesaw capacitive sensors on the I²C bus. With several sensors in the same pot
+    /// their readings are averaged.
+    Seesaw(&'static [Seesaw]),
+}
+
+impl SoilSensor {
+    /// Typical samples of the sensor in dry and in wet soil, for use until it has been
+    /// calibrated in the soil of the pot.
+    pub const fn calibration(&self) -> SoilCalibration {
+        match self {
+            // A capacitive probe powered at 3.3 V.
+            SoilSensor::Analog => SoilCalibration {
+                dry: 11400,
+                wet: 5500,
+            },
+            SoilSensor::Seesaw(_) => SoilCalibration {
+                dry: 300,
+                wet: 1000,
+            },
+        }
+    }
+
+    pub async fn read<I: I2c>(
+        &self,
+        adc: &mut OneShot<'_>,
+        pin: &mut P0_04,
+        i2c: &mut I,
+    ) -> Result<Reading, SoilError<I::Error>> {
+        match *self {
+            SoilSensor::Analog => Ok(Reading {
+                sample: Pin::new(adc).sample(pin).await,
+                temperature: None,
+            }),
+            SoilSensor::Seesaw(sensors) => {
+                if sensors.is_empty() {
+                    return Err(SoilError::NoSensors);
+                }
+                let mut moisture = 0;
+                let mut temperature = 0.0;
+                for sensor in sensors {
+                    let m = sensor.moisture(i2c).await?;
+                    let t = sensor.temperature(i2c).await.map_err(SoilError::I2c)?;
+                    log::debug!("Seesaw at {:#x}: {}, {} °C", sensor.address, m, t);
+                    moisture += m as i32;
+                    temperature += t;
+                }
+                let count = sensors.len();
+                Ok(Reading {
+                    sample: (moisture / count as i32) as i16,
+                    temperature: Some(temperature / count as f32),
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::clock;
+use core::future::Future;
+use core::pin::Pin;
+use drogue_device::*;
+use embassy::time::{Duration, Instant, Timer};
+use embassy_nrf::{gpio::Pin as _, peripherals::P0_00};
+use nrf52833_pac::PWM0;
+
+/// PWM clock after the prescaler.
+const PWM_CLOCK: u32 = 1_000_000;
+/// COUNTERTOP is 15 bits wide, so the lowest tone is about 31 Hz.
+const MAX_COUNTERTOP: u32 = 0x7FFF;
+
+/// Duty cycle buffer read by the PWM through EasyDMA, so it must live in RAM.
+static mut DUTY: [u16; 1] = [0];
+
+/// A tone of the given frequency in Hz, or silence if the frequency is 0. Frequencies
+/// below the lowest the PWM can produce play as the lowest tone.
+#[derive(Clone, Copy)]
+pub struct Tone {
+    pub frequency: u32,
+    pub duration: Duration,
+}
+
+impl Tone {
+    pub const fn new(frequency: u32, millis: u64) -> Self {
+        Self {
+            frequency,
+            duration: Duration::from_millis(millis),
+        }
+    }
+}
+
+/// Hours of the day, in local time, during which the speaker stays silent. The
+/// period may wrap around midnight.
+#[derive(Clone, Copy)]
+pub struct QuietHours {
+    pub from: u8,
+    pub to: u8,
+    /// Offset of local time from UTC in seconds.
+    pub utc_offset: i32,
+}
+
+impl QuietHours {
+    fn is_quiet(&self) -> bool {
+        match clock::time_of_day(self.utc_offset) {
+            Some(seconds) => {
+                let hour = (seconds / 3600) as u8;
+                if self.from <= self.to {
+                    hour >= self.from && hour < self.to
+                } else {
+                    hour >= self.from || hour < self.to
+                }
+            }
+            // Without knowing the time, better to sound the alarm than to let the plant dry out.
+            None => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SoundCommand {
+    /// Play the alarm, unless snoozed or within quiet hours.
+    Alarm,
+    /// Keep the alarm silent for the snooze period.
+    Snooze,
+}
+
+/// Plays tone sequences on the micro:bit v2 speaker.
+pub struct Speaker {
+    pwm: PWM0,
+    _pin: P0_00,
+    alarm: &'static [Tone],
+    quiet_hours: Option<QuietHours>,
+    snooze: Duration,
+    snoozed_until: Option<Instant>,
+}
+
+impl Speaker {
+    pub fn new(
+        pwm: PWM0,
+        pin: P0_00,
+        alarm: &'static [Tone],
+        quiet_hours: Option<QuietHours>,
+        snooze: Duration,
+    ) -> Self {
+        pwm.psel.out[0].write(|w| unsafe { w.bits(pin.psel_bits()) });
+        pwm.mode.write(|w| w.updown().up());
+        pwm.prescaler.write(|w| w.prescaler().div_16());
+        pwm.decoder
+            .write(|w| w.load().common().mode().refresh_count());
+        pwm.loop_.write(|w| w.cnt().disabled());
+        pwm.seq0.refresh.write(|w| unsafe { w.bits(0) });
+        pwm.seq0.enddelay.write(|w| unsafe { w.bits(0) });
+        Self {
+            pwm,
+            _pin: pin,
+            alarm,
+            quiet_hours,
+            snooze,
+            snoozed_until: None,
+        }
+    }
+
+    async fn play(&mut self, tones: &[Tone]) {
+        self.pwm.enable.write(|w| w.enable().enabled());
+        for tone in tones {
+            if tone.frequency == 0 {
+                self.pwm.tasks_stop.write(|w| unsafe { w.bits(1) });
+            } else {
+                // Square wave at the tone frequency. The output keeps the last value of
+                // the sequence until stopped.
+                let top = core::cmp::min(PWM_CLOCK / tone.frequency, MAX_COUNTERTOP) as u16;
+                unsafe {
+                    DUTY[0] = top / 2;
+                    self.pwm.seq0.ptr.write(|w| w.bits(DUTY.as_ptr() as u32));
+                }
+                self.pwm.seq0.cnt.write(|w| unsafe { w.bits(1) });
+                self.pwm
+                    .countertop
+                    .write(|w| unsafe { w.countertop().bits(top) });
+                self.pwm.tasks_seqstart[0].write(|w| unsafe { w.bits(1) });
+            }
+            Timer::after(tone.duration).await;
+        }
+        self.pwm.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.pwm.enable.write(|w| w.enable().disabled());
+    }
+}
+
+impl Actor for Speaker {
+    type Configuration = ();
+
+    type Message<'m> = SoundCommand;
+    type OnStartFuture<'m> = impl Future<Output = ()> + 'm;
+    type OnMessageFuture<'m> = impl Future<Output = ()> + 'm;
+
+    fn on_mount(&mut self, _: Address<'static, Self>, _: Self::Configuration) {}
+
+    fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
+        async move {}
+    }
+
+    fn on_message<'m>(
+        self: Pin<&'m mut Self>,
+        message: Self::Message<'m>,
+    ) -> Self::OnMessageFuture<'m> {
+        async move {
+            let this = unsafe { self.get_unchecked_mut() };
+            match message {
+                SoundCommand::Alarm => {
+                    if let Some(until) = this.snoozed_until {
+                        if Instant::now() < until {
+                            log::debug!("Alarm snoozed");
+                            return;
+                        }
+                        this.snoozed_until.take();
+                    }
+                    if this.quiet_hours.map(|q| q.is_quiet()).unwrap_or(false) {
+                        log::debug!("Alarm silenced during quiet hours");
+                        return;
+                    }
+                    log::info!("Sounding alarm");
+                    let alarm = this.alarm;
+                    this.play(alarm).await;
+                }
+                SoundCommand::Snooze => {
+                    log::info!("Alarm snoozed");
+                    this.snoozed_until.replace(Instant::now() + this.snooze);
+                }
+            }
+        }
+    }
+}
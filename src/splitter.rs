@@ -3,12 +3,15 @@ use core::future::Future;
 use core::pin::Pin;
 use drogue_device::*;
 
+/// Forwards each message to two actors, converting it to the message type of each.
 #[rustfmt::skip]
 pub struct Splitter<'a, M, A, B>
 where
     M: Copy,
-    A: Actor<Message<'a> = M> + 'static,
-    B: Actor<Message<'a> = M> + 'static,
+    A: Actor + 'static,
+    B: Actor + 'static,
+    A::Message<'a>: From<M>,
+    B::Message<'a>: From<M>,
 {
     a: Option<Address<'static, A>>,
     b: Option<Address<'static, B>>,
@@ -19,8 +22,10 @@ where
 impl<'a, M, A, B> Splitter<'a, M, A, B>
 where
     M: Copy,
-    A: Actor<Message<'a> = M> + 'static,
-    B: Actor<Message<'a> = M> + 'static,
+    A: Actor + 'static,
+    B: Actor + 'static,
+    A::Message<'a>: From<M>,
+    B::Message<'a>: From<M>,
 {
     pub fn new() -> Self {
         Self {
@@ -35,8 +40,10 @@ where
 impl<'a, M, A, B> Actor for Splitter<'a, M, A, B>
 where
     M: Copy,
-    A: Actor<Message<'a> = M> + 'static,
-    B: Actor<Message<'a> = M> + 'static,
+    A: Actor + 'static,
+    B: Actor + 'static,
+    A::Message<'a>: From<M>,
+    B::Message<'a>: From<M>,
 {
     type Configuration = (Address<'static, A>, Address<'static, B>);
 
@@ -61,10 +68,10 @@ where
     ) -> Self::OnMessageFuture<'m> {
         async move {
             if let Some(a) = self.a.as_ref() {
-                a.request(message).unwrap().await;
+                a.request(message.into()).unwrap().await;
             }
             if let Some(b) = self.b.as_ref() {
-                b.request(message).unwrap().await;
+                b.request(message.into()).unwrap().await;
             }
         }
     }
@@ -0,0 +1,397 @@
+use super::clock::SECONDS_PER_DAY;
+use super::rules::Metric;
+use heapless::{consts, ArrayLength, Vec};
+use serde::Serialize;
+
+/// The metrics kept statistics for, in the order of their windows.
+pub const METRICS: [Metric; 6] = [
+    Metric::Moisture,
+    Metric::Temperature,
+    Metric::Humidity,
+    Metric::DewPoint,
+    Metric::AbsoluteHumidity,
+    Metric::Vpd,
+];
+
+type MaxMetrics = consts::U6;
+
+/// Seconds covered by each bucket of the last hour.
+const HOUR_BUCKET: u32 = 300;
+/// Seconds covered by each bucket of the last 24 hours, which also make up the days.
+const DAY_BUCKET: u32 = 3600;
+
+/// Minimum, maximum and mean of a metric over some time.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Stat {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+/// Readings added up within a bucket.
+#[derive(Clone, Copy)]
+struct Aggregate {
+    count: u16,
+    sum: f32,
+    min: f32,
+    max: f32,
+}
+
+impl Aggregate {
+    const EMPTY: Self = Self {
+        count: 0,
+        sum: 0.0,
+        min: 0.0,
+        max: 0.0,
+    };
+
+    fn add(&mut self, value: f32) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max {
+            self.max = value;
+        }
+        self.count = self.count.saturating_add(1);
+        self.sum += value;
+    }
+}
+
+/// Readings within the bucket numbered `slot`, counting buckets since 1970-01-01.
+#[derive(Clone, Copy)]
+struct Bucket {
+    slot: u32,
+    aggregate: Aggregate,
+}
+
+/// Readings over the last `N` buckets, each covering the same number of seconds.
+///
+/// Buckets are reused in turn and tell which slot they hold, so buckets left over from
+/// before a gap in the readings are never mistaken for recent ones.
+struct Window<N>
+where
+    N: ArrayLength<Bucket>,
+{
+    length: u32,
+    buckets: Vec<Bucket, N>,
+}
+
+impl<N> Window<N>
+where
+    N: ArrayLength<Bucket>,
+{
+    /// Create a window of buckets covering `length` seconds each.
+    fn new(length: u32) -> Self {
+        let mut buckets = Vec::new();
+        while buckets
+            .push(Bucket {
+                slot: u32::MAX,
+                aggregate: Aggregate::EMPTY,
+            })
+            .is_ok()
+        {}
+        Self { length, buckets }
+    }
+
+    fn add(&mut self, time: u32, value: f32) {
+        let slot = time / self.length;
+        let len = self.buckets.len() as u32;
+        let bucket = &mut self.buckets[(slot % len) as usize];
+        if bucket.slot != slot {
+            bucket.slot = slot;
+            bucket.aggregate = Aggregate::EMPTY;
+        }
+        bucket.aggregate.add(value);
+    }
+
+    /// Statistics over the buckets from slot `first` to `last`.
+    ///
+    /// The mean weighs each bucket with readings equally, so readings taken more often
+    /// while sampling fast don't skew it, and buckets without readings are left out.
+    fn stat(&self, first: u32, last: u32) -> Option<Stat> {
+        let mut stat: Option<Stat> = None;
+        let mut buckets = 0;
+        for bucket in self.buckets.iter() {
+            let aggregate = &bucket.aggregate;
+            if bucket.slot < first || bucket.slot > last || aggregate.count == 0 {
+                continue;
+            }
+            let mean = aggregate.sum / aggregate.count as f32;
+            buckets += 1;
+            stat = Some(match stat {
+                None => Stat {
+                    min: aggregate.min,
+                    max: aggregate.max,
+                    mean,
+                },
+                Some(s) => Stat {
+                    min: s.min.min(aggregate.min),
+                    max: s.max.max(aggregate.max),
+                    mean: s.mean + (mean - s.mean) / buckets as f32,
+                },
+            });
+        }
+        stat
+    }
+
+    /// Statistics over the window up to `time`.
+    fn latest(&self, time: u32) -> Option<Stat> {
+        let last = time / self.length;
+        self.stat((last + 1).saturating_sub(self.buckets.len() as u32), last)
+    }
+
+    /// Statistics over the day numbered `day`, as far as the window reaches back.
+    fn day(&self, day: u32) -> Option<Stat> {
+        let first = day * (SECONDS_PER_DAY / self.length);
+        self.stat(first, first + SECONDS_PER_DAY / self.length - 1)
+    }
+}
+
+/// Statistics of each metric over a day or the last 24 hours.
+#[derive(Clone, Copy, Serialize)]
+pub struct Summary {
+    /// Days since 1970-01-01 in local time, or in uptime until the clock is set.
+    pub day: u32,
+    pub moisture: Option<Stat>,
+    pub temperature: Option<Stat>,
+    pub humidity: Option<Stat>,
+    pub dew_point: Option<Stat>,
+    pub absolute_humidity: Option<Stat>,
+    pub vpd: Option<Stat>,
+}
+
+impl Summary {
+    fn new<F>(day: u32, stat: F) -> Self
+    where
+        F: Fn(usize) -> Option<Stat>,
+    {
+        let mut summary = Self {
+            day,
+            moisture: None,
+            temperature: None,
+            humidity: None,
+            dew_point: None,
+            absolute_humidity: None,
+            vpd: None,
+        };
+        for (i, metric) in METRICS.iter().enumerate() {
+            let field = match metric {
+                Metric::Moisture => &mut summary.moisture,
+                Metric::Temperature => &mut summary.temperature,
+                Metric::Humidity => &mut summary.humidity,
+                Metric::DewPoint => &mut summary.dew_point,
+                Metric::AbsoluteHumidity => &mut summary.absolute_humidity,
+                Metric::Vpd => &mut summary.vpd,
+            };
+            *field = stat(i);
+        }
+        summary
+    }
+}
+
+/// Rolling statistics of each metric over the last hour in 5 minute buckets, and over
+/// the last 24 hours in hourly buckets. The hourly buckets also make up the statistics
+/// of the day, which are summarized once the next day starts.
+pub struct Statistics {
+    hour: Vec<Window<consts::U12>, MaxMetrics>,
+    day: Vec<Window<consts::U24>, MaxMetrics>,
+    today: Option<u32>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        let mut hour = Vec::new();
+        let mut day = Vec::new();
+        for _ in METRICS.iter() {
+            hour.push(Window::new(HOUR_BUCKET)).ok();
+            day.push(Window::new(DAY_BUCKET)).ok();
+        }
+        Self {
+            hour,
+            day,
+            today: None,
+        }
+    }
+
+    /// Add the readings at `time` seconds, in local time. Returns the summary of the
+    /// previous day with readings once they start on a new day.
+    pub fn add<F>(&mut self, reading: F, time: u32) -> Option<Summary>
+    where
+        F: Fn(Metric) -> Option<f32>,
+    {
+        let today = time / SECONDS_PER_DAY;
+        // Summarize before the readings of the new day take over buckets of the last.
+        let summary = match self.today {
+            Some(day) if day != today => Some(Summary::new(day, |i| self.day[i].day(day))),
+            _ => None,
+        };
+        self.today.replace(today);
+
+        for (i, metric) in METRICS.iter().enumerate() {
+            if let Some(value) = reading(*metric) {
+                self.hour[i].add(time, value);
+                self.day[i].add(time, value);
+            }
+        }
+        summary
+    }
+
+    /// Statistics of the metric over the last hour up to `time`.
+    pub fn last_hour(&self, metric: Metric, time: u32) -> Option<Stat> {
+        let i = METRICS.iter().position(|m| *m == metric)?;
+        self.hour[i].latest(time)
+    }
+
+    /// Statistics of the metric over the last 24 hours up to `time`.
+    pub fn last_day(&self, metric: Metric, time: u32) -> Option<Stat> {
+        let i = METRICS.iter().position(|m| *m == metric)?;
+        self.day[i].latest(time)
+    }
+
+    /// Summary of the last 24 hours up to `time`.
+    pub fn summary(&self, time: u32) -> Summary {
+        Summary::new(time / SECONDS_PER_DAY, |i| self.day[i].latest(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_near;
+
+    const HOUR: u32 = 3600;
+
+    fn assert_stat(stat: Option<Stat>, min: f32, max: f32, mean: f32) {
+        let stat = stat.expect("no statistics");
+        assert_near(stat.min, min, 1e-4);
+        assert_near(stat.max, max, 1e-4);
+        assert_near(stat.mean, mean, 1e-4);
+    }
+
+    fn temperature(value: f32) -> impl Fn(Metric) -> Option<f32> {
+        move |metric| match metric {
+            Metric::Temperature => Some(value),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn buckets_weigh_equally() {
+        let mut window: Window<consts::U12> = Window::new(HOUR_BUCKET);
+        for _ in 0..4 {
+            window.add(0, 1.0);
+        }
+        window.add(HOUR_BUCKET, 5.0);
+        window.add(HOUR_BUCKET + 60, 7.0);
+        assert_stat(window.latest(HOUR_BUCKET), 1.0, 7.0, 3.5);
+    }
+
+    #[test]
+    fn latest_from_the_start() {
+        let mut window: Window<consts::U12> = Window::new(HOUR_BUCKET);
+        assert!(window.latest(0).is_none());
+        window.add(0, 2.0);
+        assert_stat(window.latest(0), 2.0, 2.0, 2.0);
+        // Empty buckets are never taken for readings, however late it is.
+        assert!(Window::<consts::U12>::new(HOUR_BUCKET)
+            .latest(u32::MAX)
+            .is_none());
+    }
+
+    #[test]
+    fn hour_gap_excludes_stale_buckets() {
+        let mut window: Window<consts::U12> = Window::new(HOUR_BUCKET);
+        window.add(0, 1.0);
+        window.add(HOUR_BUCKET, 3.0);
+        assert_stat(window.latest(11 * HOUR_BUCKET), 1.0, 3.0, 2.0);
+        // Out of the window, though still in their buckets.
+        assert!(window.latest(13 * HOUR_BUCKET).is_none());
+        // Twenty buckets on, the reading lands between the stale ones.
+        window.add(20 * HOUR_BUCKET, 10.0);
+        assert_stat(window.latest(20 * HOUR_BUCKET), 10.0, 10.0, 10.0);
+        // A reading in the same position a full turn later replaces the old one.
+        window.add(12 * HOUR_BUCKET, 4.0);
+        assert_stat(window.latest(20 * HOUR_BUCKET), 4.0, 10.0, 7.0);
+    }
+
+    #[test]
+    fn day_gap_excludes_stale_buckets() {
+        let mut statistics = Statistics::new();
+        statistics.add(temperature(20.0), 0);
+        statistics.add(temperature(22.0), HOUR);
+        assert_stat(
+            statistics.last_day(Metric::Temperature, 2 * HOUR),
+            20.0,
+            22.0,
+            21.0,
+        );
+        assert!(statistics.last_day(Metric::Humidity, 2 * HOUR).is_none());
+        // The hour window no longer reaches back to the first reading.
+        assert_stat(
+            statistics.last_hour(Metric::Temperature, 2 * HOUR - 1),
+            22.0,
+            22.0,
+            22.0,
+        );
+
+        statistics.add(temperature(15.0), 30 * HOUR);
+        assert_stat(
+            statistics.last_day(Metric::Temperature, 30 * HOUR),
+            15.0,
+            15.0,
+            15.0,
+        );
+        assert_stat(
+            statistics.last_hour(Metric::Temperature, 30 * HOUR),
+            15.0,
+            15.0,
+            15.0,
+        );
+    }
+
+    #[test]
+    fn previous_day_summarized() {
+        let mut statistics = Statistics::new();
+        for hour in 0..24 {
+            let summary = statistics.add(temperature(hour as f32), hour * HOUR);
+            assert!(summary.is_none());
+        }
+        let summary = statistics
+            .add(temperature(30.0), SECONDS_PER_DAY)
+            .expect("no summary");
+        assert_eq!(summary.day, 0);
+        assert_stat(summary.temperature, 0.0, 23.0, 11.5);
+        assert!(summary.moisture.is_none());
+        // Only once, at the first reading of the new day.
+        assert!(statistics
+            .add(temperature(30.0), SECONDS_PER_DAY + 1)
+            .is_none());
+
+        // Hours 2 to 23 of the last day and the first hour of this one.
+        let summary = statistics.summary(SECONDS_PER_DAY + HOUR);
+        assert_eq!(summary.day, 1);
+        assert_stat(summary.temperature, 2.0, 30.0, (275.0 + 30.0) / 23.0);
+    }
+
+    #[test]
+    fn skipped_days() {
+        let mut statistics = Statistics::new();
+        statistics.add(temperature(10.0), 5 * SECONDS_PER_DAY + 6 * HOUR);
+        statistics.add(temperature(12.0), 5 * SECONDS_PER_DAY + 18 * HOUR);
+        let summary = statistics
+            .add(temperature(20.0), 8 * SECONDS_PER_DAY + 6 * HOUR)
+            .expect("no summary");
+        // The last day with readings, with none from the days in between.
+        assert_eq!(summary.day, 5);
+        assert_stat(summary.temperature, 10.0, 12.0, 11.0);
+        assert_stat(
+            statistics.last_day(Metric::Temperature, 8 * SECONDS_PER_DAY + 6 * HOUR),
+            20.0,
+            20.0,
+            20.0,
+        );
+        assert!(statistics
+            .add(temperature(20.0), 8 * SECONDS_PER_DAY + 7 * HOUR)
+            .is_none());
+    }
+}
@@ -0,0 +1,87 @@
+use core::task::Poll;
+use embassy::{interrupt::InterruptExt, util::AtomicWaker};
+use embassy_nrf::interrupt;
+use futures::future::poll_fn;
+use nrf52833_pac::TEMP;
+#[cfg(feature = "gateway")]
+use nrf_softdevice::{raw, Softdevice};
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Die temperature sensor of the nRF52833.
+///
+/// The die runs a few degrees warmer than the air around the board, so readings need an
+/// offset to stand in for the air temperature.
+pub struct Temp {
+    source: Source,
+}
+
+enum Source {
+    Peripheral(TEMP),
+    /// The SoftDevice keeps the sensor to itself while enabled, so it is read through it.
+    #[cfg(feature = "gateway")]
+    Softdevice,
+}
+
+impl Temp {
+    pub fn new(temp: TEMP, irq: interrupt::TEMP) -> Self {
+        temp.intenclr.write(|w| w.datardy().clear());
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+        Self {
+            source: Source::Peripheral(temp),
+        }
+    }
+
+    /// Read the sensor through the enabled SoftDevice.
+    #[cfg(feature = "gateway")]
+    pub fn softdevice(_sd: &'static Softdevice) -> Self {
+        Self {
+            source: Source::Softdevice,
+        }
+    }
+
+    unsafe fn on_interrupt(_: *mut ()) {
+        // Mask the interrupt until the next measurement, the event is cleared once read.
+        let temp = &*TEMP::ptr();
+        temp.intenclr.write(|w| w.datardy().clear());
+        WAKER.wake();
+    }
+
+    /// Measure the die temperature in °C, with a resolution of 0.25 °C.
+    pub async fn read(&mut self) -> f32 {
+        let temp = match &self.source {
+            Source::Peripheral(temp) => temp,
+            #[cfg(feature = "gateway")]
+            Source::Softdevice => {
+                // The SoftDevice waits for the measurement, which takes about 36 µs.
+                let mut value = 0;
+                let ret = unsafe { raw::sd_temp_get(&mut value) };
+                if ret != raw::NRF_SUCCESS {
+                    log::warn!("Error reading temperature from SoftDevice: {}", ret);
+                }
+                return value as f32 / 4.0;
+            }
+        };
+
+        temp.events_datardy.write(|w| unsafe { w.bits(0) });
+        temp.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            if temp.events_datardy.read().bits() != 0 {
+                Poll::Ready(())
+            } else {
+                temp.intenset.write(|w| w.datardy().set());
+                Poll::Pending
+            }
+        })
+        .await;
+
+        temp.events_datardy.write(|w| unsafe { w.bits(0) });
+        let value = temp.temp.read().bits() as i32;
+        temp.tasks_stop.write(|w| unsafe { w.bits(1) });
+        value as f32 / 4.0
+    }
+}
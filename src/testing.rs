@@ -0,0 +1,13 @@
+//! Helpers shared by the host tests.
+
+/// Assert that a computed value lies within `tolerance` of the expected one.
+pub fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+    let error = actual - expected;
+    assert!(
+        error <= tolerance && error >= -tolerance,
+        "{} is not within {} of {}",
+        actual,
+        tolerance,
+        expected
+    );
+}
@@ -0,0 +1,205 @@
+use drogue_device::actors::led::matrix::{fonts::frame_5x5, Frame};
+use heapless::{consts, Vec};
+
+/// Glyphs drawn in the 5x5 grid, one byte per row with the leftmost column in bit 4.
+///
+/// Blank columns at either side of a glyph are trimmed when scrolling, so narrow
+/// glyphs take up less space.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b01100, 0b10010, 0b10010, 0b10010, 0b01100],
+        '1' => [0b01000, 0b11000, 0b01000, 0b01000, 0b11100],
+        '2' => [0b11100, 0b00010, 0b01100, 0b10000, 0b11110],
+        '3' => [0b11110, 0b00010, 0b00100, 0b10010, 0b01100],
+        '4' => [0b00110, 0b01010, 0b10010, 0b11110, 0b00010],
+        '5' => [0b11110, 0b10000, 0b11100, 0b00010, 0b11100],
+        '6' => [0b01100, 0b10000, 0b11100, 0b10010, 0b01100],
+        '7' => [0b11110, 0b00010, 0b00100, 0b01000, 0b01000],
+        '8' => [0b01100, 0b10010, 0b01100, 0b10010, 0b01100],
+        '9' => [0b01100, 0b10010, 0b01110, 0b00010, 0b01100],
+        'A' => [0b01100, 0b10010, 0b11110, 0b10010, 0b10010],
+        'B' => [0b11100, 0b10010, 0b11100, 0b10010, 0b11100],
+        'C' => [0b01110, 0b10000, 0b10000, 0b10000, 0b01110],
+        'D' => [0b11100, 0b10010, 0b10010, 0b10010, 0b11100],
+        'E' => [0b11110, 0b10000, 0b11100, 0b10000, 0b11110],
+        'F' => [0b11110, 0b10000, 0b11100, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10000, 0b10110, 0b10010, 0b01110],
+        'H' => [0b10010, 0b10010, 0b11110, 0b10010, 0b10010],
+        'I' => [0b11100, 0b01000, 0b01000, 0b01000, 0b11100],
+        'J' => [0b00110, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10010, 0b10100, 0b11000, 0b10100, 0b10010],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b11110],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10001, 0b10001],
+        'N' => [0b10001, 0b11001, 0b10101, 0b10011, 0b10001],
+        'O' => [0b01100, 0b10010, 0b10010, 0b10010, 0b01100],
+        'P' => [0b11100, 0b10010, 0b11100, 0b10000, 0b10000],
+        'Q' => [0b01100, 0b10010, 0b10010, 0b10100, 0b01010],
+        'R' => [0b11100, 0b10010, 0b11100, 0b10100, 0b10010],
+        'S' => [0b01110, 0b10000, 0b01100, 0b00010, 0b11100],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10010, 0b10010, 0b10010, 0b10010, 0b01100],
+        'V' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10101, 0b11011, 0b10001],
+        'X' => [0b10001, 0b01010, 0b00100, 0b01010, 0b10001],
+        'Y' => [0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11110, 0b00010, 0b00100, 0b01000, 0b11110],
+        '.' => [0b00000, 0b00000, 0b00000, 0b00000, 0b10000],
+        ',' => [0b00000, 0b00000, 0b00000, 0b01000, 0b10000],
+        ':' => [0b00000, 0b10000, 0b00000, 0b10000, 0b00000],
+        '-' => [0b00000, 0b00000, 0b11100, 0b00000, 0b00000],
+        '+' => [0b00000, 0b01000, 0b11100, 0b01000, 0b00000],
+        '%' => [0b11001, 0b11010, 0b00100, 0b01011, 0b10011],
+        '°' => [0b01000, 0b10100, 0b01000, 0b00000, 0b00000],
+        '!' => [0b10000, 0b10000, 0b10000, 0b00000, 0b10000],
+        _ => [0b01100, 0b10010, 0b00100, 0b00000, 0b00100],
+    }
+}
+
+/// Blank columns the text scrolls out of view through.
+const BLANK_END: usize = 4;
+/// Columns of "...", ending the text in place of what does not fit.
+const ELLIPSIS: [u8; 6] = [0x10, 0, 0x10, 0, 0x10, 0];
+
+/// Append the columns of a glyph, each with the top row in bit 0, trimming blank
+/// columns at either side. Nothing is appended if they do not fit.
+fn push_glyph(columns: &mut Vec<u8, consts::U256>, c: char) -> Result<(), ()> {
+    if c == ' ' {
+        return columns.extend_from_slice(&[0, 0]);
+    }
+
+    let rows = glyph(c);
+    let mut glyph_columns = [0; 5];
+    for (x, column) in glyph_columns.iter_mut().enumerate() {
+        for (y, row) in rows.iter().enumerate() {
+            *column |= ((row >> (4 - x)) & 1) << y;
+        }
+    }
+
+    let first = glyph_columns.iter().position(|c| *c != 0).unwrap_or(0);
+    let last = glyph_columns.iter().rposition(|c| *c != 0).unwrap_or(0);
+    columns.extend_from_slice(&glyph_columns[first..=last])
+}
+
+/// Text scrolling from right to left across the 5x5 matrix, one column per step.
+///
+/// Text too long to fit is cut off after the last glyph that fits, ending in "...".
+pub struct ScrollingText {
+    columns: Vec<u8, consts::U256>,
+    offset: usize,
+}
+
+impl ScrollingText {
+    pub fn new(text: &str) -> Self {
+        let mut columns: Vec<u8, consts::U256> = Vec::new();
+        // Start and end with an empty display, so the text scrolls in and out of view.
+        columns.extend_from_slice(&[0; 5]).ok();
+        let end = columns.capacity() - BLANK_END;
+        // End of the last glyph leaving room for the ellipsis after it.
+        let mut fits = columns.len();
+        for c in text.chars() {
+            let pushed = push_glyph(&mut columns, c).is_ok() && columns.push(0).is_ok();
+            if !pushed || columns.len() > end {
+                columns.truncate(fits);
+                columns.extend_from_slice(&ELLIPSIS).ok();
+                break;
+            }
+            if columns.len() <= end - ELLIPSIS.len() {
+                fits = columns.len();
+            }
+        }
+        columns.extend_from_slice(&[0; BLANK_END]).ok();
+        Self { columns, offset: 0 }
+    }
+}
+
+impl Iterator for ScrollingText {
+    type Item = Frame<5, 5>;
+
+    fn next(&mut self) -> Option<Frame<5, 5>> {
+        let window = self.columns.get(self.offset..self.offset + 5)?;
+        self.offset += 1;
+
+        let mut rows = [0; 5];
+        for (y, row) in rows.iter_mut().enumerate() {
+            for (x, column) in window.iter().enumerate() {
+                *row |= ((column >> y) & 1) << (4 - x);
+            }
+        }
+        Some(frame_5x5(&rows))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyph_columns() {
+        let mut columns = Vec::new();
+        push_glyph(&mut columns, '1').unwrap();
+        // The blank columns at the right of the glyph are trimmed.
+        assert_eq!(&columns[..], &[0b10010, 0b11111, 0b10000]);
+
+        columns.clear();
+        push_glyph(&mut columns, '.').unwrap();
+        push_glyph(&mut columns, ' ').unwrap();
+        push_glyph(&mut columns, 'm').unwrap();
+        assert_eq!(
+            &columns[..],
+            &[0b10000, 0, 0, 0b11111, 0b00010, 0b00100, 0b00010, 0b11111]
+        );
+    }
+
+    #[test]
+    fn text_layout() {
+        let text = ScrollingText::new("1.");
+        assert_eq!(
+            &text.columns[..],
+            &[0, 0, 0, 0, 0, 0b10010, 0b11111, 0b10000, 0, 0b10000, 0, 0, 0, 0, 0]
+        );
+        // One frame for each step until the last column has scrolled out of view.
+        assert_eq!(text.count(), 11);
+        assert_eq!(ScrollingText::new("").count(), 5);
+    }
+
+    /// The columns of text made of `n` zeros, each 4 columns wide and followed by a blank.
+    fn zeros(n: usize) -> Vec<u8, consts::U256> {
+        let mut text = heapless::String::<consts::U64>::new();
+        for _ in 0..n {
+            text.push('0').unwrap();
+        }
+        ScrollingText::new(&text).columns
+    }
+
+    #[test]
+    fn text_that_fits_is_kept() {
+        let columns = zeros(49);
+        assert_eq!(columns.len(), 5 + 49 * 5 + BLANK_END);
+        assert_eq!(
+            &columns[columns.len() - 9..],
+            &[0b01110, 0b10001, 0b10001, 0b01110, 0, 0, 0, 0, 0][..]
+        );
+    }
+
+    #[test]
+    fn long_text_truncated() {
+        let columns = zeros(50);
+        // The last glyph leaving room for the ellipsis is kept whole.
+        assert_eq!(columns.len(), 5 + 48 * 5 + ELLIPSIS.len() + BLANK_END);
+        let (text, end) = columns.split_at(5 + 48 * 5);
+        assert_eq!(
+            &text[text.len() - 5..],
+            &[0b01110, 0b10001, 0b10001, 0b01110, 0]
+        );
+        assert_eq!(&end[..ELLIPSIS.len()], &ELLIPSIS);
+        assert_eq!(&end[ELLIPSIS.len()..], &[0; BLANK_END]);
+
+        let mut long = heapless::String::<consts::U128>::new();
+        for _ in 0..64 {
+            long.push_str("HI").unwrap();
+        }
+        let columns = ScrollingText::new(&long).columns;
+        assert!(columns.len() <= columns.capacity());
+        assert_eq!(&columns[columns.len() - 10..columns.len() - 4], &ELLIPSIS);
+    }
+}
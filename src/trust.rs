@@ -0,0 +1,247 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use drogue_tls::{SignatureScheme, TlsError, Verifier};
+use p256::ecdsa::{signature::Verifier as _, Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+/// Base64 encoded SHA-256 hash of the server's DER encoded SubjectPublicKeyInfo, as
+/// produced by:
+///
+/// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`
+const SERVER_KEY: &str = include_str!(concat!(env!("OUT_DIR"), "/config/server-key.sha256"));
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_BIT_STRING: u8 = 0x03;
+const DER_EXPLICIT_0: u8 = 0xA0;
+
+/// AlgorithmIdentifier of an elliptic curve public key on P-256.
+const EC_P256_ALGORITHM: &[u8] = &[
+    0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48,
+    0xce, 0x3d, 0x03, 0x01, 0x07,
+];
+/// Length of an uncompressed P-256 point.
+const EC_P256_POINT_LEN: usize = 65;
+
+/// Prefix of the content signed in the server CertificateVerify (RFC 8446, 4.4.3).
+const CERTIFICATE_VERIFY_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify\0";
+const CERTIFICATE_VERIFY_PADDING: usize = 64;
+
+/// Why the server could not be trusted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrustError {
+    /// The server presented a key other than the pinned one.
+    PinMismatch,
+    /// The server key is not a P-256 key, so its signature cannot be checked.
+    UnsupportedKey,
+    /// The handshake was not signed by the pinned key.
+    BadSignature,
+}
+
+/// Verifies the server certificate against the public key pinned at build time.
+///
+/// Pinning the key rather than the certificate allows the server certificate to be
+/// renewed without rebuilding the firmware, as long as the key is kept.
+///
+/// The certificate is public, so a matching key alone proves nothing. The
+/// CertificateVerify signature is therefore checked against the same key as well,
+/// binding the handshake to the holder of the pinned private key. Only P-256 server
+/// keys are supported.
+pub struct PinnedKey {
+    hash: [u8; 32],
+    /// Uncompressed point of the server key accepted in the current handshake.
+    server_key: Cell<Option<[u8; EC_P256_POINT_LEN]>>,
+    error: Cell<Option<TrustError>>,
+    rejected: AtomicBool,
+}
+
+impl PinnedKey {
+    /// Returns the pinned key configured at build time, if any.
+    pub fn configured() -> Option<Self> {
+        let encoded = SERVER_KEY.trim();
+        if encoded.is_empty() {
+            return None;
+        }
+        let mut hash = [0; 32];
+        match base64::decode_config_slice(encoded, base64::STANDARD, &mut hash) {
+            Ok(32) => Some(Self {
+                hash,
+                server_key: Cell::new(None),
+                error: Cell::new(None),
+                rejected: AtomicBool::new(false),
+            }),
+            _ => panic!("config/server-key.sha256 must contain a base64 encoded SHA-256 hash"),
+        }
+    }
+
+    /// Returns why the last handshake was aborted, if the server could not be verified,
+    /// clearing it.
+    ///
+    /// The TLS socket reports failed handshakes as plain connection errors, so the
+    /// reason is kept here for the client to pick up.
+    pub fn take_error(&self) -> Option<TrustError> {
+        if self.rejected.swap(false, Ordering::SeqCst) {
+            self.error.take()
+        } else {
+            None
+        }
+    }
+
+    fn reject(&self, error: TrustError) -> TlsError {
+        self.server_key.set(None);
+        self.error.set(Some(error));
+        self.rejected.store(true, Ordering::SeqCst);
+        TlsError::InvalidCertificate
+    }
+}
+
+impl Verifier for PinnedKey {
+    fn verify(&self, _server_name: &str, certificate: &[u8]) -> Result<(), TlsError> {
+        let spki = subject_public_key_info(certificate)
+            .filter(|spki| Sha256::digest(spki).as_slice() == self.hash)
+            .ok_or_else(|| self.reject(TrustError::PinMismatch))?;
+        let point = ec_p256_point(spki).ok_or_else(|| self.reject(TrustError::UnsupportedKey))?;
+        self.server_key.set(Some(point));
+        Ok(())
+    }
+
+    fn verify_signature(
+        &self,
+        transcript_hash: &[u8],
+        scheme: SignatureScheme,
+        signature: &[u8],
+    ) -> Result<(), TlsError> {
+        let point = self
+            .server_key
+            .take()
+            .ok_or_else(|| self.reject(TrustError::BadSignature))?;
+        if scheme != SignatureScheme::EcdsaSecp256r1Sha256 {
+            return Err(self.reject(TrustError::UnsupportedKey));
+        }
+
+        let mut content = [0x20; CERTIFICATE_VERIFY_PADDING + 34 + 64];
+        let start = CERTIFICATE_VERIFY_PADDING;
+        let end = start + CERTIFICATE_VERIFY_CONTEXT.len();
+        content[start..end].copy_from_slice(CERTIFICATE_VERIFY_CONTEXT);
+        let content = content
+            .get_mut(..end + transcript_hash.len())
+            .ok_or_else(|| self.reject(TrustError::BadSignature))?;
+        content[end..].copy_from_slice(transcript_hash);
+
+        let verified = match (
+            VerifyingKey::from_sec1_bytes(&point),
+            Signature::from_der(signature),
+        ) {
+            (Ok(key), Ok(signature)) => key.verify(content, &signature).is_ok(),
+            _ => false,
+        };
+        if verified {
+            Ok(())
+        } else {
+            Err(self.reject(TrustError::BadSignature))
+        }
+    }
+}
+
+/// Extract the uncompressed point from the SubjectPublicKeyInfo of a P-256 key.
+fn ec_p256_point(spki: &[u8]) -> Option<[u8; EC_P256_POINT_LEN]> {
+    let (_, spki) = der_value(spki, DER_SEQUENCE)?;
+    let key = spki.strip_prefix(EC_P256_ALGORITHM)?;
+    let (_, bits) = der_value(key, DER_BIT_STRING)?;
+    // No unused bits, followed by the uncompressed point.
+    match bits {
+        [0, 0x04, ..] if bits.len() == 1 + EC_P256_POINT_LEN => {
+            let mut point = [0; EC_P256_POINT_LEN];
+            point.copy_from_slice(&bits[1..]);
+            Some(point)
+        }
+        _ => None,
+    }
+}
+
+/// Locate the encoded SubjectPublicKeyInfo inside a DER encoded X.509 certificate.
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate) = der_value(certificate, DER_SEQUENCE)?;
+    let (_, mut tbs) = der_value(certificate, DER_SEQUENCE)?;
+
+    // Skip the optional version, serial number, signature algorithm, issuer, validity
+    // and subject. What remains starts with the SubjectPublicKeyInfo.
+    if tbs.first() == Some(&DER_EXPLICIT_0) {
+        tbs = der_skip(tbs)?;
+    }
+    for _ in 0..5 {
+        tbs = der_skip(tbs)?;
+    }
+    let (len, _) = der_value(tbs, DER_SEQUENCE)?;
+    tbs.get(..len)
+}
+
+/// Decode a DER element with the expected tag, returning the total length of the
+/// element and its contents.
+fn der_value(data: &[u8], tag: u8) -> Option<(usize, &[u8])> {
+    if *data.first()? != tag {
+        return None;
+    }
+    let (header, len) = match *data.get(1)? {
+        len if len < 0x80 => (2, len as usize),
+        0x81 => (3, *data.get(2)? as usize),
+        0x82 => (4, (*data.get(2)? as usize) << 8 | *data.get(3)? as usize),
+        _ => return None,
+    };
+    let value = data.get(header..header + len)?;
+    Some((header + len, value))
+}
+
+/// Skip over the DER element at the start of data, whatever its tag.
+fn der_skip(data: &[u8]) -> Option<&[u8]> {
+    let (len, _) = der_value(data, *data.first()?)?;
+    data.get(len..)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Self-signed certificates and their public keys, generated with:
+    //
+    // `openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes -subj /CN=planteboks.example -outform der`
+    // `openssl x509 -inform der -pubkey -noout | openssl pkey -pubin -outform der`
+    const P256_CERT: &[u8] = include_bytes!("../testdata/server-p256.der");
+    const P256_SPKI: &[u8] = include_bytes!("../testdata/server-p256.spki.der");
+    const RSA_CERT: &[u8] = include_bytes!("../testdata/server-rsa.der");
+    const RSA_SPKI: &[u8] = include_bytes!("../testdata/server-rsa.spki.der");
+
+    #[test]
+    fn spki_from_certificate() {
+        assert_eq!(subject_public_key_info(P256_CERT), Some(P256_SPKI));
+        assert_eq!(subject_public_key_info(RSA_CERT), Some(RSA_SPKI));
+    }
+
+    #[test]
+    fn p256_point() {
+        let point = ec_p256_point(P256_SPKI).unwrap();
+        assert_eq!(point[0], 0x04);
+        assert_eq!(
+            &point[..],
+            &P256_SPKI[P256_SPKI.len() - EC_P256_POINT_LEN..]
+        );
+    }
+
+    #[test]
+    fn rsa_key_unsupported() {
+        assert_eq!(ec_p256_point(RSA_SPKI), None);
+    }
+
+    #[test]
+    fn truncated_certificate() {
+        for len in 0..P256_CERT.len() {
+            assert_eq!(subject_public_key_info(&P256_CERT[..len]), None);
+        }
+    }
+
+    #[test]
+    fn not_a_certificate() {
+        assert_eq!(subject_public_key_info(P256_SPKI), None);
+        assert_eq!(subject_public_key_info(&[0x30, 0x84, 0, 0, 0, 1, 0]), None);
+        assert_eq!(ec_p256_point(&P256_SPKI[..P256_SPKI.len() - 1]), None);
+    }
+}
@@ -0,0 +1,324 @@
+use super::flow::FlowMeter;
+use super::plant_monitor::{Event, Measurement};
+use super::rules::{Alert, Severity};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use drogue_device::*;
+use embassy::time::{Duration, Instant, Timer};
+use embassy_nrf::{
+    gpio::{Input, Output},
+    peripherals::{P0_03, P1_02},
+};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+const IDLE: u8 = 0;
+/// Pumping or waiting for the water to soak in.
+const BUSY: u8 = 1;
+/// The water has soaked in and a new reading is needed to continue.
+const DUE: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(IDLE);
+
+/// Set when the lockouts are to be cleared before the next reading.
+static RESUME: AtomicBool = AtomicBool::new(false);
+
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+/// How often the flow meter is checked while the pump runs.
+const FLOW_POLL: Duration = Duration::from_millis(100);
+
+/// Whether the pump is running or the water is still soaking in, so readings taken now
+/// would not tell how much more is needed.
+pub fn is_busy() -> bool {
+    STATE.load(Ordering::Relaxed) == BUSY
+}
+
+/// Whether a reading is needed to continue watering, clearing the flag.
+pub fn take_due() -> bool {
+    STATE
+        .compare_exchange(DUE, IDLE, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+}
+
+/// Clear the probe fault and no flow lockouts with the next reading, once the probe
+/// has been put back in the soil or the pump and hose have been seen to.
+pub fn resume() {
+    RESUME.store(true, Ordering::Relaxed);
+}
+
+/// Limits keeping a faulty probe or an empty tank from flooding the room.
+#[derive(Clone, Copy)]
+pub struct Interlocks {
+    /// Longest the pump may run in one pulse, whatever the configured pulse.
+    pub max_pulse: Duration,
+    pub max_pulses_per_day: u8,
+    /// Pulses after which the soil moisture must have risen by `min_rise` percent
+    /// since watering started. Otherwise the probe is assumed to be out of the soil and
+    /// watering is locked out until it reads wet again or watering is resumed.
+    pub check_after: u8,
+    pub min_rise: u8,
+}
+
+/// Waters the plant through a pump or valve switched by a relay or MOSFET.
+///
+/// Watering starts once the soil moisture drops below the lower threshold. The pump
+/// then runs in pulses, leaving the water time to soak in after each one so the probe
+/// sees it, until the moisture reaches the upper threshold.
+///
+/// With a flow meter each pulse runs until it has delivered the pulse volume, rather
+/// than for the pulse duration. No flow while pumping means the pump runs dry or the
+/// hose is blocked, and watering is blocked until the soil reads wet again.
+///
+/// A probe fault or no flow lockout is also cleared when the server sends
+/// `{"resume_watering":true}`, as a probe put back into dry soil never reads wet by
+/// itself.
+///
+/// An optional float switch pulling the reservoir pin low when the tank is empty
+/// blocks pumping until the tank is refilled.
+#[rustfmt::skip]
+pub struct Watering<'a, A>
+where
+    A: Actor<Message<'a> = Event> + 'a,
+{
+    pump: Output<'static, P0_03>,
+    reservoir: Option<Input<'static, P1_02>>,
+    flow: Option<FlowMeter>,
+    start_below: u8,
+    stop_at: u8,
+    pulse: Duration,
+    pulse_volume: u32,
+    soak: Duration,
+    interlocks: Interlocks,
+    sink: Option<Address<'a, A>>,
+    active: bool,
+    started_at: u8,
+    pulses: u8,
+    day_started: Instant,
+    pulses_today: u8,
+    limited: bool,
+    locked_out: bool,
+    no_flow: bool,
+    tank_empty: bool,
+}
+
+#[rustfmt::skip]
+impl<'a, A> Watering<'a, A>
+where
+    A: Actor<Message<'a> = Event> + 'a,
+{
+    pub fn new(
+        pump: Output<'static, P0_03>,
+        reservoir: Option<Input<'static, P1_02>>,
+        flow: Option<FlowMeter>,
+        start_below: u8,
+        stop_at: u8,
+        pulse: Duration,
+        pulse_volume: u32,
+        soak: Duration,
+        interlocks: Interlocks,
+    ) -> Self {
+        Self {
+            pump,
+            reservoir,
+            flow,
+            start_below,
+            stop_at,
+            pulse,
+            pulse_volume,
+            soak,
+            interlocks,
+            sink: None,
+            active: false,
+            started_at: 0,
+            pulses: 0,
+            day_started: Instant::now(),
+            pulses_today: 0,
+            limited: false,
+            locked_out: false,
+            no_flow: false,
+            tank_empty: false,
+        }
+    }
+
+    async fn alert(&mut self, rule: &'static str, severity: Severity, active: bool, value: f32) {
+        if active {
+            log::warn!("Watering interlock: {}", rule);
+        } else {
+            log::info!("Watering interlock cleared: {}", rule);
+        }
+        let alert = Alert {
+            rule,
+            severity,
+            active,
+            value,
+        };
+        self.sink
+            .unwrap()
+            .request(Event::Alert(alert))
+            .unwrap()
+            .await;
+    }
+
+    /// Update the interlocks with the reading, returning whether the pump may run.
+    async fn check(&mut self, moisture: u8) -> bool {
+        if Instant::now() - self.day_started >= DAY {
+            self.day_started = Instant::now();
+            self.pulses_today = 0;
+            if self.limited {
+                self.limited = false;
+                self.alert("PUMP DAILY", Severity::Warning, false, 0.0)
+                    .await;
+            }
+        }
+
+        let resume = RESUME.swap(false, Ordering::Relaxed);
+        if resume {
+            log::info!("Resuming watering");
+        }
+        // The probe responds again, so it is back in the soil.
+        if self.locked_out && (resume || moisture >= self.stop_at) {
+            self.locked_out = false;
+            self.alert("PROBE FAULT", Severity::Critical, false, moisture as f32)
+                .await;
+        }
+        if self.no_flow && (resume || moisture >= self.stop_at) {
+            self.no_flow = false;
+            self.alert("NO FLOW", Severity::Critical, false, moisture as f32)
+                .await;
+        }
+
+        let empty = match self.reservoir.as_ref() {
+            Some(reservoir) => reservoir.is_low().unwrap_or(false),
+            None => false,
+        };
+        if empty != self.tank_empty {
+            self.tank_empty = empty;
+            self.alert("TANK EMPTY", Severity::Critical, empty, 0.0)
+                .await;
+        }
+
+        if !self.active || self.locked_out || self.no_flow || self.tank_empty {
+            return false;
+        }
+
+        if self.pulses >= self.interlocks.check_after
+            && moisture < self.started_at.saturating_add(self.interlocks.min_rise)
+        {
+            self.locked_out = true;
+            self.active = false;
+            self.alert("PROBE FAULT", Severity::Critical, true, moisture as f32)
+                .await;
+            return false;
+        }
+
+        if self.pulses_today >= self.interlocks.max_pulses_per_day {
+            if !self.limited {
+                self.limited = true;
+                let pulses = self.pulses_today as f32;
+                self.alert("PUMP DAILY", Severity::Warning, true, pulses)
+                    .await;
+            }
+            return false;
+        }
+        true
+    }
+
+    async fn run_pulse(&mut self) {
+        STATE.store(BUSY, Ordering::Relaxed);
+        // Water still draining after the last pulse is not part of this one.
+        if let Some(flow) = self.flow.as_mut() {
+            flow.take();
+        }
+        self.pump.set_high().ok();
+        let started = Instant::now();
+        let delivered = match self.flow.as_mut() {
+            Some(flow) => {
+                let mut delivered = 0;
+                while delivered < self.pulse_volume
+                    && Instant::now() - started < self.interlocks.max_pulse
+                {
+                    Timer::after(FLOW_POLL).await;
+                    delivered += flow.take();
+                }
+                Some(delivered)
+            }
+            None => {
+                Timer::after(core::cmp::min(self.pulse, self.interlocks.max_pulse)).await;
+                None
+            }
+        };
+        self.pump.set_low().ok();
+        self.pulses += 1;
+        self.pulses_today += 1;
+        log::info!(
+            "Pump ran for {} ms, delivering {:?} ml",
+            (Instant::now() - started).as_millis(),
+            delivered
+        );
+
+        if delivered == Some(0) {
+            STATE.store(IDLE, Ordering::Relaxed);
+            self.no_flow = true;
+            self.active = false;
+            self.alert("NO FLOW", Severity::Critical, true, 0.0).await;
+            return;
+        }
+        Timer::after(self.soak).await;
+        STATE.store(DUE, Ordering::Relaxed);
+    }
+}
+
+#[rustfmt::skip]
+impl<'a, A> Actor for Watering<'a, A>
+where
+    A: Actor<Message<'a> = Event> + 'a,
+{
+    type Configuration = Address<'a, A>;
+
+    #[rustfmt::skip]
+    type Message<'m> where 'a: 'm = Measurement;
+    #[rustfmt::skip]
+    type OnStartFuture<'m> where 'a: 'm = impl Future<Output = ()> + 'm;
+    #[rustfmt::skip]
+    type OnMessageFuture<'m> where 'a: 'm = impl Future<Output = ()> + 'm;
+
+    fn on_mount(&mut self, _: Address<'static, Self>, config: Self::Configuration) {
+        self.sink.replace(config);
+    }
+
+    fn on_start<'m>(self: Pin<&'m mut Self>) -> Self::OnStartFuture<'m> {
+        async move {
+            let this = unsafe { self.get_unchecked_mut() };
+            this.pump.set_low().ok();
+            if this.flow.is_none() && this.pulse > this.interlocks.max_pulse {
+                let pulse = this.pulse.as_secs() as f32;
+                this.alert("PUMP LIMIT", Severity::Warning, true, pulse)
+                    .await;
+            }
+        }
+    }
+
+    fn on_message<'m>(
+        self: Pin<&'m mut Self>,
+        message: Self::Message<'m>,
+    ) -> Self::OnMessageFuture<'m> {
+        async move {
+            let this = unsafe { self.get_unchecked_mut() };
+            let moisture = message.moisture;
+            if !this.active && !this.locked_out && !this.no_flow && moisture < this.start_below {
+                log::info!("Soil moisture {} %, start watering", moisture);
+                this.active = true;
+                this.started_at = moisture;
+                this.pulses = 0;
+            } else if this.active && moisture >= this.stop_at {
+                log::info!("Soil moisture {} %, done watering", moisture);
+                this.active = false;
+            }
+
+            if this.check(moisture).await {
+                this.run_pulse().await;
+            }
+        }
+    }
+}